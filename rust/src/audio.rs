@@ -0,0 +1,80 @@
+// Volume settings and how sounds are pitched, apart from the engine's audio server.
+//
+// Settings are saved as text: a header line with the format version, then one tab
+// separated line per bus with its name and volume from 0 to 1.
+
+use crate::versioned::{read_versioned, write_header, FormatError};
+
+const HEADER: &str = "GOOP AUDIO";
+const VERSION: u32 = 1;
+
+// Semitones each kill in a chain is pitched above the one before it
+const CHAIN_STEP: f32 = 2.0;
+// Highest a chain climbs, in semitones
+const CHAIN_MAX: f32 = 24.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AudioSettings {
+    // Linear volumes from 0 (muted) to 1
+    pub sfx: f32,
+    pub music: f32,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            sfx: 0.8,
+            music: 0.6,
+        }
+    }
+}
+
+impl AudioSettings {
+    pub fn encode(&self) -> String {
+        write_header(HEADER, VERSION) + &format!("sfx\t{}\nmusic\t{}\n", self.sfx, self.music)
+    }
+
+    // Volumes that are missing or cannot be read keep their defaults
+    pub fn decode(text: &str) -> Result<Self, FormatError> {
+        let (version, lines) = read_versioned(text, HEADER)?;
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut settings = Self::default();
+        for line in lines {
+            let Some((bus, volume)) = line.split_once('\t') else {
+                continue;
+            };
+            let Ok(volume) = volume.parse::<f32>() else {
+                continue;
+            };
+            match bus {
+                "sfx" => settings.sfx = volume.clamp(0.0, 1.0),
+                "music" => settings.music = volume.clamp(0.0, 1.0),
+                _ => {}
+            }
+        }
+        Ok(settings)
+    }
+}
+
+// Decibels for a linear volume. Silence is left to muting the bus.
+pub fn volume_to_db(volume: f32) -> f32 {
+    20.0 * volume.max(0.0001).log10()
+}
+
+fn semitones(count: f32) -> f32 {
+    2.0_f32.powf(count / 12.0)
+}
+
+// Pitch scale of the `chain_index`th kill of a shot, starting at 0
+pub fn chain_pitch(chain_index: u32) -> f32 {
+    semitones((chain_index as f32 * CHAIN_STEP).min(CHAIN_MAX))
+}
+
+// Pitch scale of a spawn in `lane`. The lanes are spread over an octave, so each
+// one sounds a little different.
+pub fn lane_pitch(lane: u32, lane_count: u32) -> f32 {
+    semitones(12.0 * lane as f32 / lane_count.max(1) as f32)
+}
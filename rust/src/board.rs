@@ -0,0 +1,98 @@
+// Board dimensions, chosen when a run starts instead of at compile time.
+
+use crate::game::Position;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoardConfig {
+    // Size of the whole field in cells
+    pub width: usize,
+    pub height: usize,
+    // Size of the square the player moves around in
+    pub center_size: usize,
+    // Size of a cell on screen in pixels
+    pub cell_size: f32,
+}
+
+impl Default for BoardConfig {
+    fn default() -> Self {
+        Self {
+            width: 18,
+            height: 12,
+            center_size: 4,
+            cell_size: 16.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BoardError {
+    EmptyCenter,
+    // The center has to leave at least one lane cell on every side
+    CenterTooLarge,
+    // Replays store each size in 16 bits
+    TooLarge,
+    InvalidCellSize(f32),
+}
+
+impl fmt::Display for BoardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::EmptyCenter => write!(f, "center size must be at least 1"),
+            Self::CenterTooLarge => write!(f, "center must leave room for lanes on every side"),
+            Self::TooLarge => write!(f, "sizes must be at most {}", u16::MAX),
+            Self::InvalidCellSize(size) => write!(f, "invalid cell size {size}"),
+        }
+    }
+}
+
+impl BoardConfig {
+    pub fn validate(&self) -> Result<(), BoardError> {
+        if self.center_size == 0 {
+            return Err(BoardError::EmptyCenter);
+        }
+        if self.width < self.center_size + 2 || self.height < self.center_size + 2 {
+            return Err(BoardError::CenterTooLarge);
+        }
+        if self.width > u16::MAX as usize || self.height > u16::MAX as usize {
+            return Err(BoardError::TooLarge);
+        }
+        if !(self.cell_size > 0.0 && self.cell_size.is_finite()) {
+            return Err(BoardError::InvalidCellSize(self.cell_size));
+        }
+        Ok(())
+    }
+
+    // With the default board, x of the center ranges from 7-10
+    pub fn min_center_x(&self) -> usize {
+        (self.width - self.center_size) / 2
+    }
+
+    pub fn max_center_x(&self) -> usize {
+        self.min_center_x() + self.center_size - 1
+    }
+
+    // With the default board, y of the center ranges from 4-7
+    pub fn min_center_y(&self) -> usize {
+        (self.height - self.center_size) / 2
+    }
+
+    pub fn max_center_y(&self) -> usize {
+        self.min_center_y() + self.center_size - 1
+    }
+
+    pub fn x_in_center(&self, x: usize) -> bool {
+        (self.min_center_x()..=self.max_center_x()).contains(&x)
+    }
+
+    pub fn y_in_center(&self, y: usize) -> bool {
+        (self.min_center_y()..=self.max_center_y()).contains(&y)
+    }
+
+    // Cells of the 3x3 square centered on `position` that lie on the board
+    pub fn square_around(&self, position: Position) -> impl Iterator<Item = Position> {
+        let xs = position.x.saturating_sub(1)..=(position.x + 1).min(self.width - 1);
+        let ys = position.y.saturating_sub(1)..=(position.y + 1).min(self.height - 1);
+        xs.flat_map(move |x| ys.clone().map(move |y| Position { x, y }))
+    }
+}
@@ -0,0 +1,262 @@
+// Computer players. A strategy looks at the game state and picks the next command,
+// the same commands a human produces with the keyboard.
+
+use crate::board::BoardConfig;
+use crate::game::*;
+use crate::palette::Palette;
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+
+pub trait Strategy {
+    // The next command to send, or `None` to wait
+    fn decide(&mut self, state: &GameState) -> Option<Command>;
+}
+
+// Goes for the shot that kills the most enemies right now
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Greedy;
+
+impl Strategy for Greedy {
+    fn decide(&mut self, state: &GameState) -> Option<Command> {
+        let best = lanes(state.board())
+            .map(|lane| (lane, expected_goops(state, lane), threat(state, lane)))
+            .max_by_key(|&(_, goops, threat)| (goops, threat))?;
+
+        // With nothing to kill, work towards clearing the most crowded lane instead
+        if best.1 == 0 {
+            return Survival.decide(state);
+        }
+        approach(state, best.0)
+    }
+}
+
+// Keeps the lane whose enemies are closest to the center under control
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Survival;
+
+impl Strategy for Survival {
+    fn decide(&mut self, state: &GameState) -> Option<Command> {
+        let (lane, _, threat) = lanes(state.board())
+            .map(|lane| (lane, expected_goops(state, lane), threat(state, lane)))
+            .max_by_key(|&(_, goops, threat)| (threat, goops))?;
+
+        if threat == 0 {
+            return None;
+        }
+        if expected_goops(state, lane) > 0 {
+            return approach(state, lane);
+        }
+
+        // Pick up the color of the lane's front enemy by swapping with another lane first
+        let (_, front) = lane_enemies(state, lane)[0];
+        let swap_lane = lanes(state.board()).find(|&other| {
+            other != lane
+                && lane_enemies(state, other)
+                    .first()
+                    .is_some_and(|(_, enemy)| {
+                        enemy.kind == PieceKind::Normal && enemy.color == front.color
+                    })
+        });
+        approach(state, swap_lane.unwrap_or(lane))
+    }
+}
+
+// Where a shot goes: the direction it travels and the row or column it travels along
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lane {
+    pub direction: Direction,
+    pub index: usize,
+}
+
+impl Lane {
+    // The lane `position` lies in, or `None` for cells in the center or a corner
+    pub fn at(board: &BoardConfig, position: Position) -> Option<Self> {
+        let Position { x, y } = position;
+        let (direction, index) = if board.y_in_center(y) && x < board.min_center_x() {
+            (Direction::Left, y)
+        } else if board.y_in_center(y) && x > board.max_center_x() {
+            (Direction::Right, y)
+        } else if board.x_in_center(x) && y < board.min_center_y() {
+            (Direction::Up, x)
+        } else if board.x_in_center(x) && y > board.max_center_y() {
+            (Direction::Down, x)
+        } else {
+            return None;
+        };
+        Some(Self { direction, index })
+    }
+
+    // The lane the player would shoot along
+    pub fn facing(player: &PlayerState) -> Self {
+        let index = match player.direction {
+            Direction::Left | Direction::Right => player.position.y,
+            Direction::Up | Direction::Down => player.position.x,
+        };
+        Self {
+            direction: player.direction,
+            index,
+        }
+    }
+
+    // Position of the lane in `lanes`
+    pub fn number(&self, board: &BoardConfig) -> usize {
+        lanes(board).position(|lane| lane == *self).unwrap_or(0)
+    }
+
+    // Cells of the lane from the center outwards
+    pub fn cells(&self, board: &BoardConfig) -> Vec<Position> {
+        let index = self.index;
+        match self.direction {
+            Direction::Left => (0..board.min_center_x())
+                .rev()
+                .map(|x| Position { x, y: index })
+                .collect(),
+            Direction::Right => (board.max_center_x() + 1..board.width)
+                .map(|x| Position { x, y: index })
+                .collect(),
+            Direction::Up => (0..board.min_center_y())
+                .rev()
+                .map(|y| Position { x: index, y })
+                .collect(),
+            Direction::Down => (board.max_center_y() + 1..board.height)
+                .map(|y| Position { x: index, y })
+                .collect(),
+        }
+    }
+
+    // True if the player can shoot along this lane from `position`
+    fn is_aligned(&self, position: Position) -> bool {
+        match self.direction {
+            Direction::Left | Direction::Right => position.y == self.index,
+            Direction::Up | Direction::Down => position.x == self.index,
+        }
+    }
+}
+
+// Rows to the left, rows to the right, columns above, then columns below the center
+pub fn lanes(board: &BoardConfig) -> impl Iterator<Item = Lane> {
+    let rows = board.min_center_y()..=board.max_center_y();
+    let columns = board.min_center_x()..=board.max_center_x();
+
+    [Direction::Left, Direction::Right]
+        .into_iter()
+        .flat_map(move |direction| rows.clone().map(move |index| Lane { direction, index }))
+        .chain(
+            [Direction::Up, Direction::Down]
+                .into_iter()
+                .flat_map(move |direction| {
+                    columns.clone().map(move |index| Lane { direction, index })
+                }),
+        )
+}
+
+// Enemies in the lane, nearest to the center first
+pub fn lane_enemies(state: &GameState, lane: Lane) -> Vec<(EnemyId, EnemyState)> {
+    lane.cells(state.board())
+        .into_iter()
+        .filter_map(|position| match state.tile(position) {
+            Tile::Enemy(enemy_id) => state.enemy(enemy_id).map(|enemy| (enemy_id, *enemy)),
+            _ => None,
+        })
+        .collect()
+}
+
+// How many enemies are waiting in the lane. The fuller it is, the sooner it reaches the center.
+pub fn threat(state: &GameState, lane: Lane) -> usize {
+    lane_enemies(state, lane).len()
+}
+
+// Enemies a shot along the lane would kill with the player's current color
+pub fn expected_goops(state: &GameState, lane: Lane) -> usize {
+    let color = state.player().color;
+    let enemies = lane_enemies(state, lane);
+
+    let mut goops = 0;
+    for (index, (_, enemy)) in enemies.iter().enumerate() {
+        match enemy.kind {
+            PieceKind::Normal if enemy.color == color => goops += 1,
+            PieceKind::Normal => break,
+            PieceKind::Bomb => return goops + 1 + bomb_targets(state, enemy.position),
+            PieceKind::Lightning => return goops + enemies.len() - index,
+            PieceKind::ColorWash => {
+                let same_color = state
+                    .enemies()
+                    .filter(|(_, other)| other.color == color && other.kind == PieceKind::Normal)
+                    .count();
+                return same_color + 1;
+            }
+        }
+    }
+    goops
+}
+
+fn bomb_targets(state: &GameState, position: Position) -> usize {
+    state
+        .board()
+        .square_around(position)
+        .filter(|&cell| cell != position && matches!(state.tile(cell), Tile::Enemy(_)))
+        .count()
+}
+
+// One step towards shooting along `lane`: line up with it, face it, then shoot
+fn approach(state: &GameState, lane: Lane) -> Option<Command> {
+    let player = state.player();
+    if !lane.is_aligned(player.position) {
+        let towards = match lane.direction {
+            Direction::Left | Direction::Right if player.position.y < lane.index => Direction::Down,
+            Direction::Left | Direction::Right => Direction::Up,
+            Direction::Up | Direction::Down if player.position.x < lane.index => Direction::Right,
+            Direction::Up | Direction::Down => Direction::Left,
+        };
+        Some(Command::Move(towards))
+    } else if player.direction != lane.direction {
+        // Moving along the lane keeps the player lined up with it
+        Some(Command::Move(lane.direction))
+    } else {
+        Some(Command::Shoot)
+    }
+}
+
+// Result of a simulated game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SoakResult {
+    pub points: u64,
+    pub goops: u32,
+    pub level: u32,
+    pub spawns: u32,
+    pub lost: bool,
+}
+
+// Plays a whole game without the engine, giving the strategy `actions_per_spawn`
+// commands between spawns. Stops when the game is lost or after `max_spawns`.
+pub fn soak(
+    strategy: &mut dyn Strategy,
+    seed: u64,
+    actions_per_spawn: u32,
+    max_spawns: u32,
+) -> SoakResult {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut state = GameState::new(BoardConfig::default(), Palette::default(), &mut rng);
+
+    let mut spawns = 0;
+    while spawns < max_spawns && !state.is_lost() {
+        let spawn = state.random_spawn(&mut rng);
+        state.spawn(spawn);
+        spawns += 1;
+
+        for _ in 0..actions_per_spawn {
+            let Some(command) = strategy.decide(&state) else {
+                break;
+            };
+            state.step(command);
+        }
+    }
+
+    SoakResult {
+        points: state.points(),
+        goops: state.goops(),
+        level: state.level().number(),
+        spawns,
+        lost: state.is_lost(),
+    }
+}
@@ -0,0 +1,909 @@
+// Engine independent game rules. Nothing in this module touches Godot, so the
+// whole simulation can be driven and inspected without a running scene.
+
+use crate::board::BoardConfig;
+use crate::level::Level;
+use crate::palette::{Color, Palette};
+pub use crate::registry::EnemyId;
+use crate::registry::Registry;
+use crate::scoring::ScoringModel;
+use rand::distributions::Standard;
+use rand::prelude::*;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Position {
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl Direction {
+    pub fn opposite(&self) -> Self {
+        match self {
+            Self::Left => Self::Right,
+            Self::Right => Self::Left,
+            Self::Up => Self::Down,
+            Self::Down => Self::Up,
+        }
+    }
+
+    pub fn delta(&self) -> (isize, isize) {
+        match self {
+            Self::Left => (-1, 0),
+            Self::Right => (1, 0),
+            Self::Up => (0, -1),
+            Self::Down => (0, 1),
+        }
+    }
+}
+
+// What an enemy does when it is shot. Power-ups go off whatever the player's color is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PieceKind {
+    #[default]
+    Normal,
+    // Clears the 3x3 square around itself
+    Bomb,
+    // Clears the whole lane behind itself
+    Lightning,
+    // Clears every enemy of the player's color
+    ColorWash,
+}
+
+// Allows pieces to be randomly generated. About one in twenty is a power-up.
+impl Distribution<PieceKind> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PieceKind {
+        match rng.gen_range(0..60) {
+            0 => PieceKind::Bomb,
+            1 => PieceKind::Lightning,
+            2 => PieceKind::ColorWash,
+            _ => PieceKind::Normal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Tile {
+    #[default]
+    None,
+    Player,
+    Enemy(EnemyId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Move(Direction),
+    Shoot,
+}
+
+// A new enemy entering the field. `direction` is the way the lane moves,
+// `position` is the edge cell the enemy appears on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpawnEvent {
+    pub direction: Direction,
+    pub position: Position,
+    pub color: Color,
+    pub kind: PieceKind,
+}
+
+// Everything that happened as a result of a command or spawn, in order.
+// Views replay these to keep the scene in sync with the state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    EnemySpawned {
+        id: EnemyId,
+        position: Position,
+        color: Color,
+        kind: PieceKind,
+    },
+    EnemyMoved {
+        id: EnemyId,
+        position: Position,
+    },
+    EnemyKilled {
+        id: EnemyId,
+        position: Position,
+        color: Color,
+        // 1 for the first kill of a shot, 2 for the second and so on
+        chain: u16,
+        // Points this kill was worth
+        points: u64,
+    },
+    EnemyRecolored {
+        id: EnemyId,
+        color: Color,
+    },
+    // A power-up was shot. The enemies it clears follow as `EnemyKilled`.
+    PowerUpTriggered {
+        id: EnemyId,
+        position: Position,
+        kind: PieceKind,
+    },
+    // Taken off the board without being shot, e.g. when a level is cleared
+    EnemyRemoved {
+        id: EnemyId,
+    },
+    PlayerTurned {
+        direction: Direction,
+    },
+    PlayerMoved {
+        position: Position,
+    },
+    PlayerRecolored {
+        color: Color,
+    },
+    ShotFired {
+        direction: Direction,
+        // The last cell the shot reached before returning
+        target: Position,
+        goops: u16,
+    },
+    ScoreChanged {
+        points: u64,
+    },
+    // The quota of `level` was met and the board has been cleared
+    LevelCompleted {
+        level: u32,
+    },
+    Lost,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnemyState {
+    pub position: Position,
+    pub color: Color,
+    pub kind: PieceKind,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerState {
+    pub position: Position,
+    pub direction: Direction,
+    pub color: Color,
+}
+
+// A place where the grid disagrees with where the player or an enemy is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inconsistency {
+    PlayerNotOnGrid(Position),
+    StrayPlayerTile(Position),
+    EnemyNotOnGrid(EnemyId, Position),
+    StrayEnemyTile(EnemyId, Position),
+}
+
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PlayerNotOnGrid(Position { x, y }) => {
+                write!(f, "player at ({x}, {y}) is missing from the grid")
+            }
+            Self::StrayPlayerTile(Position { x, y }) => {
+                write!(f, "grid has a player at ({x}, {y}) where there is none")
+            }
+            Self::EnemyNotOnGrid(id, Position { x, y }) => {
+                write!(
+                    f,
+                    "enemy {} at ({x}, {y}) is missing from the grid",
+                    id.to_bits()
+                )
+            }
+            Self::StrayEnemyTile(id, Position { x, y }) => {
+                write!(
+                    f,
+                    "grid has enemy {} at ({x}, {y}) where there is none",
+                    id.to_bits()
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GameState {
+    board: BoardConfig,
+    // Indexed as `grid[x][y]`
+    grid: Vec<Vec<Tile>>,
+    // Only changed through `add_enemy`, `move_enemy` and `remove_enemy`, which keep it
+    // in step with the grid
+    enemies: Registry<EnemyState>,
+    player: PlayerState,
+    last_direction: Option<Direction>,
+    level: Level,
+    palette: Palette,
+    scoring: ScoringModel,
+    points: u64,
+    goops: u32,
+    lost: bool,
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        let board = BoardConfig::default();
+        Self {
+            board,
+            grid: vec![vec![Tile::None; board.height]; board.width],
+            enemies: Registry::default(),
+            player: PlayerState::default(),
+            last_direction: None,
+            level: Level::default(),
+            palette: Palette::default(),
+            scoring: ScoringModel::default(),
+            points: 0,
+            goops: 0,
+            lost: false,
+        }
+    }
+}
+
+impl GameState {
+    // Starts a new game with the player at a random position in the center.
+    // `board` has to pass `BoardConfig::validate`.
+    pub fn new<R: Rng + ?Sized>(board: BoardConfig, palette: Palette, rng: &mut R) -> Self {
+        let x = rng.gen_range(board.min_center_x()..=board.max_center_x());
+        let y = rng.gen_range(board.min_center_y()..=board.max_center_y());
+
+        let mut state = Self {
+            board,
+            grid: vec![vec![Tile::None; board.height]; board.width],
+            player: PlayerState {
+                position: Position { x, y },
+                direction: Direction::Up,
+                color: palette.choose(Level::default().spec().colors, rng),
+            },
+            palette,
+            ..Default::default()
+        };
+        state.grid[x][y] = Tile::Player;
+        state
+    }
+
+    pub fn board(&self) -> &BoardConfig {
+        &self.board
+    }
+
+    pub fn tile(&self, position: Position) -> Tile {
+        self.grid[position.x][position.y]
+    }
+
+    pub fn enemy(&self, enemy_id: EnemyId) -> Option<&EnemyState> {
+        self.enemies.get(enemy_id)
+    }
+
+    pub fn enemies(&self) -> impl Iterator<Item = (EnemyId, &EnemyState)> {
+        self.enemies.iter()
+    }
+
+    pub fn player(&self) -> &PlayerState {
+        &self.player
+    }
+
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    pub fn set_scoring(&mut self, scoring: ScoringModel) {
+        self.scoring = scoring;
+    }
+
+    pub fn points(&self) -> u64 {
+        self.points
+    }
+
+    pub fn goops(&self) -> u32 {
+        self.goops
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    // Compares the grid with the player and enemy records, for catching bugs that
+    // let them drift apart. Empty while they agree.
+    pub fn inconsistencies(&self) -> Vec<Inconsistency> {
+        let mut found = Vec::new();
+
+        for (x, column) in self.grid.iter().enumerate() {
+            for (y, &tile) in column.iter().enumerate() {
+                let position = Position { x, y };
+                match tile {
+                    Tile::Player if position != self.player.position => {
+                        found.push(Inconsistency::StrayPlayerTile(position));
+                    }
+                    Tile::Enemy(enemy_id)
+                        if !self
+                            .enemy(enemy_id)
+                            .is_some_and(|enemy| enemy.position == position) =>
+                    {
+                        found.push(Inconsistency::StrayEnemyTile(enemy_id, position));
+                    }
+                    _ => (),
+                }
+            }
+        }
+
+        // The enemy that ends a run can take the player's cell
+        if !self.lost && self.tile(self.player.position) != Tile::Player {
+            found.push(Inconsistency::PlayerNotOnGrid(self.player.position));
+        }
+        for (enemy_id, enemy) in self.enemies() {
+            if self.tile(enemy.position) != Tile::Enemy(enemy_id) {
+                found.push(Inconsistency::EnemyNotOnGrid(enemy_id, enemy.position));
+            }
+        }
+        found
+    }
+
+    // How close the enemy nearest to the center is, from 0 with no enemies on the
+    // board to 1 when one is right next to the center
+    pub fn threat(&self) -> f64 {
+        let board = self.board;
+        let mut threat: f64 = 0.0;
+
+        for (x, column) in self.grid.iter().enumerate() {
+            for (y, tile) in column.iter().enumerate() {
+                if !matches!(tile, Tile::Enemy(_)) {
+                    continue;
+                }
+                // Cells left between the enemy and the center, and how many its lane has
+                let (gap, depth) = match (board.x_in_center(x), board.y_in_center(y)) {
+                    (true, true) => return 1.0,
+                    (true, false) if y < board.min_center_y() => {
+                        (board.min_center_y() - 1 - y, board.min_center_y())
+                    }
+                    (true, false) => (
+                        y - board.max_center_y() - 1,
+                        board.height - board.max_center_y() - 1,
+                    ),
+                    (false, true) if x < board.min_center_x() => {
+                        (board.min_center_x() - 1 - x, board.min_center_x())
+                    }
+                    (false, true) => (
+                        x - board.max_center_x() - 1,
+                        board.width - board.max_center_x() - 1,
+                    ),
+                    // No lane runs through the corners
+                    (false, false) => continue,
+                };
+                threat = threat.max(1.0 - gap as f64 / depth as f64);
+            }
+        }
+        threat
+    }
+
+    // Seconds between spawns on the current level
+    pub fn spawn_interval(&self) -> f64 {
+        self.level.spec().spawn_interval
+    }
+
+    // Chooses where the next enemy appears and what color it has
+    pub fn random_spawn<R: Rng + ?Sized>(&self, rng: &mut R) -> SpawnEvent {
+        let board = self.board;
+        let columns = board.min_center_x()..=board.max_center_x();
+        let rows = board.min_center_y()..=board.max_center_y();
+        let mut lanes = Vec::new();
+
+        // Enemies cannot spawn in the same quadrant twice in a row
+        if self.last_direction != Some(Direction::Down) {
+            // Creates a list of positions at the top of the field
+            // Enemy is facing the down direction
+            lanes.extend(
+                columns
+                    .clone()
+                    .map(|x| (Direction::Down, Position { x, y: 0 })),
+            )
+        }
+
+        if self.last_direction != Some(Direction::Up) {
+            lanes.extend(columns.map(|x| {
+                (
+                    Direction::Up,
+                    Position {
+                        x,
+                        y: board.height - 1,
+                    },
+                )
+            }))
+        }
+
+        if self.last_direction != Some(Direction::Right) {
+            lanes.extend(
+                rows.clone()
+                    .map(|y| (Direction::Right, Position { x: 0, y })),
+            )
+        }
+
+        if self.last_direction != Some(Direction::Left) {
+            lanes.extend(rows.map(|y| {
+                (
+                    Direction::Left,
+                    Position {
+                        x: board.width - 1,
+                        y,
+                    },
+                )
+            }))
+        }
+
+        // Choose a random position at the end of one of the quadrants
+        let (direction, position) = *lanes.choose(rng).unwrap();
+        SpawnEvent {
+            direction,
+            position,
+            color: self.palette.choose(self.level.spec().colors, rng),
+            kind: rng.gen(),
+        }
+    }
+
+    pub fn spawn(&mut self, spawn: SpawnEvent) -> Vec<Event> {
+        let mut events = Vec::new();
+        if self.lost {
+            return events;
+        }
+
+        self.last_direction = Some(spawn.direction);
+
+        // Move all enemies in the lane one step closer to the center,
+        // starting with the one nearest to it
+        let lane = self.lane_cells(spawn.direction, spawn.position);
+        for pair in lane.windows(2).rev() {
+            let (from, to) = (pair[0], pair[1]);
+            if let Tile::Enemy(enemy_id) = self.tile(from) {
+                self.move_enemy(enemy_id, to);
+                events.push(Event::EnemyMoved {
+                    id: enemy_id,
+                    position: to,
+                });
+            }
+        }
+
+        // Add the enemy to the field data
+        let enemy_id = self.add_enemy(EnemyState {
+            position: spawn.position,
+            color: spawn.color,
+            kind: spawn.kind,
+        });
+        events.push(Event::EnemySpawned {
+            id: enemy_id,
+            position: spawn.position,
+            color: spawn.color,
+            kind: spawn.kind,
+        });
+
+        // If any enemy has reached the center, the game is lost
+        if self.check_lose_condition() {
+            self.lost = true;
+            events.push(Event::Lost);
+        }
+
+        events
+    }
+
+    pub fn step(&mut self, command: Command) -> Vec<Event> {
+        let mut events = Vec::new();
+        if self.lost {
+            return events;
+        }
+
+        match command {
+            Command::Move(direction) => self.move_player(direction, &mut events),
+            Command::Shoot => self.shoot(&mut events),
+        }
+
+        events
+    }
+
+    fn move_player(&mut self, direction: Direction, events: &mut Vec<Event>) {
+        self.player.direction = direction;
+        events.push(Event::PlayerTurned { direction });
+
+        // Prevent player from leaving the center
+        let (dx, dy) = direction.delta();
+        let board = self.board;
+        let x = (self.player.position.x as isize + dx)
+            .clamp(board.min_center_x() as isize, board.max_center_x() as isize)
+            as usize;
+        let y = (self.player.position.y as isize + dy)
+            .clamp(board.min_center_y() as isize, board.max_center_y() as isize)
+            as usize;
+
+        let from = self.player.position;
+        let to = Position { x, y };
+
+        self.grid[from.x][from.y] = Tile::None;
+        self.grid[to.x][to.y] = Tile::Player;
+        self.player.position = to;
+        events.push(Event::PlayerMoved { position: to });
+    }
+
+    fn shoot(&mut self, events: &mut Vec<Event>) {
+        let direction = self.player.direction;
+        let mut position = self.player.position;
+        let mut goops = 0;
+
+        // Kill every enemy of the same color until one can no longer be found
+        while let Some((enemy_id, enemy_position)) = self.find_enemy(position, direction) {
+            // Updating the position reduces the required computation for `find_enemy`
+            position = enemy_position;
+
+            let enemy = self.enemies[enemy_id];
+            if enemy.kind != PieceKind::Normal {
+                // Power-ups end the shot
+                self.trigger_power_up(enemy_id, direction, &mut goops, events);
+                break;
+            }
+
+            let enemy_color = enemy.color;
+            if self.player.color == enemy_color {
+                self.kill_enemy(enemy_id, &mut goops, events);
+            } else {
+                // If the color does not match, swap the player and enemy color, then stop
+                let player_color = self.player.color;
+                self.player.color = enemy_color;
+                if let Some(enemy) = self.enemies.get_mut(enemy_id) {
+                    enemy.color = player_color;
+                }
+                events.push(Event::PlayerRecolored { color: enemy_color });
+                events.push(Event::EnemyRecolored {
+                    id: enemy_id,
+                    color: player_color,
+                });
+                break;
+            }
+        }
+
+        events.push(Event::ShotFired {
+            direction,
+            target: position,
+            goops,
+        });
+
+        if goops > 0 {
+            self.goops = self.goops.saturating_add(goops as u32);
+            events.push(Event::ScoreChanged {
+                points: self.points,
+            });
+
+            if self.level.add_kills(goops) {
+                self.complete_level(events);
+            }
+        }
+    }
+
+    fn trigger_power_up(
+        &mut self,
+        enemy_id: EnemyId,
+        direction: Direction,
+        goops: &mut u16,
+        events: &mut Vec<Event>,
+    ) {
+        let enemy = self.enemies[enemy_id];
+        events.push(Event::PowerUpTriggered {
+            id: enemy_id,
+            position: enemy.position,
+            kind: enemy.kind,
+        });
+        self.kill_enemy(enemy_id, goops, events);
+
+        let targets: Vec<EnemyId> = match enemy.kind {
+            PieceKind::Normal => Vec::new(),
+            PieceKind::Bomb => self
+                .board
+                .square_around(enemy.position)
+                .filter_map(|position| match self.tile(position) {
+                    Tile::Enemy(enemy_id) => Some(enemy_id),
+                    _ => None,
+                })
+                .collect(),
+            PieceKind::Lightning => {
+                let mut targets = Vec::new();
+                let mut position = enemy.position;
+                while let Some((enemy_id, enemy_position)) = self.find_enemy(position, direction) {
+                    targets.push(enemy_id);
+                    position = enemy_position;
+                }
+                targets
+            }
+            PieceKind::ColorWash => self
+                .enemies
+                .iter()
+                .filter(|(_, enemy)| enemy.color == self.player.color)
+                .map(|(enemy_id, _)| enemy_id)
+                .collect(),
+        };
+
+        for enemy_id in targets {
+            self.kill_enemy(enemy_id, goops, events);
+        }
+    }
+
+    // Removes a shot enemy and awards points for it as the next kill in the chain
+    fn kill_enemy(&mut self, enemy_id: EnemyId, goops: &mut u16, events: &mut Vec<Event>) {
+        let enemy = self.enemies[enemy_id];
+        *goops += 1;
+        // Killing multiple enemies in one move gives bonus points
+        let points = self.scoring.kill_points(*goops);
+        self.points = self.points.saturating_add(points);
+
+        self.remove_enemy(enemy_id);
+        events.push(Event::EnemyKilled {
+            id: enemy_id,
+            position: enemy.position,
+            color: enemy.color,
+            chain: *goops,
+            points,
+        });
+    }
+
+    // Clears the board and moves on to the next level
+    fn complete_level(&mut self, events: &mut Vec<Event>) {
+        let remaining: Vec<EnemyId> = self.enemies.iter().map(|(enemy_id, _)| enemy_id).collect();
+        for enemy_id in remaining {
+            self.remove_enemy(enemy_id);
+            events.push(Event::EnemyRemoved { id: enemy_id });
+        }
+
+        events.push(Event::LevelCompleted {
+            level: self.level.number(),
+        });
+        self.level.advance();
+        self.last_direction = None;
+    }
+
+    // Check if an enemy has reached the center
+    fn check_lose_condition(&self) -> bool {
+        for x in self.board.min_center_x()..=self.board.max_center_x() {
+            for y in self.board.min_center_y()..=self.board.max_center_y() {
+                if let Tile::Enemy(_) = self.grid[x][y] {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    // This function finds the closest enemy from `position` in `direction`
+    fn find_enemy(&self, position: Position, direction: Direction) -> Option<(EnemyId, Position)> {
+        let (dx, dy) = direction.delta();
+        let mut x = position.x as isize + dx;
+        let mut y = position.y as isize + dy;
+
+        while (0..self.board.width as isize).contains(&x)
+            && (0..self.board.height as isize).contains(&y)
+        {
+            let position = Position {
+                x: x as usize,
+                y: y as usize,
+            };
+            // Stop at the first enemy found
+            if let Tile::Enemy(enemy_id) = self.tile(position) {
+                return Some((enemy_id, position));
+            }
+            x += dx;
+            y += dy;
+        }
+        None
+    }
+
+    fn add_enemy(&mut self, enemy: EnemyState) -> EnemyId {
+        let Position { x, y } = enemy.position;
+        let enemy_id = self.enemies.insert(enemy);
+        self.grid[x][y] = Tile::Enemy(enemy_id);
+        enemy_id
+    }
+
+    fn move_enemy(&mut self, enemy_id: EnemyId, to: Position) {
+        let Some(enemy) = self.enemies.get_mut(enemy_id) else {
+            return;
+        };
+        let from = enemy.position;
+        enemy.position = to;
+        self.grid[from.x][from.y] = Tile::None;
+        self.grid[to.x][to.y] = Tile::Enemy(enemy_id);
+    }
+
+    fn remove_enemy(&mut self, enemy_id: EnemyId) {
+        if let Some(enemy) = self.enemies.remove(enemy_id) {
+            let Position { x, y } = enemy.position;
+            self.grid[x][y] = Tile::None;
+        }
+    }
+
+    // Cells of the lane that starts at `edge` and runs in `direction` up to the
+    // first cell of the center, ordered from the edge inwards
+    fn lane_cells(&self, direction: Direction, edge: Position) -> Vec<Position> {
+        let board = self.board;
+        match direction {
+            Direction::Right => (0..=board.min_center_x())
+                .map(|x| Position { x, y: edge.y })
+                .collect(),
+            Direction::Left => (board.max_center_x()..board.width)
+                .rev()
+                .map(|x| Position { x, y: edge.y })
+                .collect(),
+            Direction::Down => (0..=board.min_center_y())
+                .map(|y| Position { x: edge.x, y })
+                .collect(),
+            Direction::Up => (board.max_center_y()..board.height)
+                .rev()
+                .map(|y| Position { x: edge.x, y })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_chacha::ChaCha8Rng;
+
+    fn new_game() -> GameState {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        GameState::new(BoardConfig::default(), Palette::default(), &mut rng)
+    }
+
+    // A color other than the player's
+    fn other_color(state: &GameState) -> Color {
+        Color((state.player().color.0 + 1) % Palette::default().color_count() as u8)
+    }
+
+    // Spawns an enemy at the top of the player's column, in the way of their shot
+    fn spawn_above(state: &mut GameState, color: Color) -> Vec<Event> {
+        let x = state.player().position.x;
+        state.spawn(SpawnEvent {
+            direction: Direction::Down,
+            position: Position { x, y: 0 },
+            color,
+            kind: PieceKind::Normal,
+        })
+    }
+
+    // Plays a game with random spawns and commands, collecting every event
+    fn play(seed: u64) -> (GameState, Vec<Event>) {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut state = GameState::new(BoardConfig::default(), Palette::default(), &mut rng);
+        let mut events = Vec::new();
+        for _ in 0..500 {
+            let spawn = state.random_spawn(&mut rng);
+            events.extend(state.spawn(spawn));
+            for _ in 0..3 {
+                let command = match rng.gen_range(0..5) {
+                    0 => Command::Move(Direction::Left),
+                    1 => Command::Move(Direction::Right),
+                    2 => Command::Move(Direction::Up),
+                    3 => Command::Move(Direction::Down),
+                    _ => Command::Shoot,
+                };
+                events.extend(state.step(command));
+            }
+            assert!(
+                state.inconsistencies().is_empty(),
+                "{:?}",
+                state.inconsistencies()
+            );
+        }
+        (state, events)
+    }
+
+    #[test]
+    fn spawn_pushes_lane_inward() {
+        let mut state = new_game();
+        let color = state.player().color;
+        let x = state.player().position.x;
+
+        let first = match spawn_above(&mut state, color)[..] {
+            [Event::EnemySpawned { id, .. }] => id,
+            ref events => panic!("unexpected events {events:?}"),
+        };
+        let events = spawn_above(&mut state, color);
+
+        assert_eq!(
+            events[0],
+            Event::EnemyMoved {
+                id: first,
+                position: Position { x, y: 1 },
+            }
+        );
+        assert_eq!(state.enemy(first).unwrap().position, Position { x, y: 1 });
+        assert!(matches!(
+            state.tile(Position { x, y: 0 }),
+            Tile::Enemy(id) if id != first
+        ));
+    }
+
+    #[test]
+    fn same_color_chain_kills_and_scores() {
+        let mut state = new_game();
+        let color = state.player().color;
+        spawn_above(&mut state, color);
+        spawn_above(&mut state, color);
+
+        let events = state.step(Command::Shoot);
+
+        let chains: Vec<u16> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::EnemyKilled { chain, .. } => Some(*chain),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(chains, [1, 2]);
+        let scoring = ScoringModel::default();
+        assert_eq!(
+            state.points(),
+            scoring.kill_points(1) + scoring.kill_points(2)
+        );
+        assert_eq!(state.goops(), 2);
+        assert_eq!(state.enemies().count(), 0);
+    }
+
+    #[test]
+    fn mismatch_swaps_colors() {
+        let mut state = new_game();
+        let player_color = state.player().color;
+        let enemy_color = other_color(&state);
+        spawn_above(&mut state, enemy_color);
+        let (enemy_id, _) = state.enemies().next().unwrap();
+
+        let events = state.step(Command::Shoot);
+
+        assert!(events.contains(&Event::PlayerRecolored { color: enemy_color }));
+        assert_eq!(state.player().color, enemy_color);
+        assert_eq!(state.enemy(enemy_id).unwrap().color, player_color);
+        assert_eq!(state.points(), 0);
+    }
+
+    #[test]
+    fn enemy_reaching_center_loses() {
+        let mut state = new_game();
+        let board = *state.board();
+        // A center column the player isn't standing in
+        let x = (board.min_center_x()..=board.max_center_x())
+            .find(|&x| x != state.player().position.x)
+            .unwrap();
+        let spawn = SpawnEvent {
+            direction: Direction::Down,
+            position: Position { x, y: 0 },
+            color: state.player().color,
+            kind: PieceKind::Normal,
+        };
+
+        // The lane is every cell above the center, so one more spawn pushes the
+        // first enemy into it
+        for _ in 0..board.min_center_y() {
+            assert!(!state.spawn(spawn).contains(&Event::Lost));
+        }
+        assert!(!state.is_lost());
+        assert!(state.spawn(spawn).contains(&Event::Lost));
+        assert!(state.is_lost());
+        assert!(state.step(Command::Shoot).is_empty());
+    }
+
+    #[test]
+    fn seeded_run_stays_consistent() {
+        for seed in 0..5 {
+            play(seed);
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_same_game() {
+        let (first, first_events) = play(7);
+        let (second, second_events) = play(7);
+
+        assert_eq!(first_events, second_events);
+        assert_eq!(first.player(), second.player());
+        assert_eq!(first.points(), second.points());
+        assert_eq!(first.goops(), second.goops());
+        assert_eq!(first.is_lost(), second.is_lost());
+        assert!(first.enemies().eq(second.enemies()));
+    }
+}
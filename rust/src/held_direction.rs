@@ -0,0 +1,205 @@
+// Turning held inputs into moves: an analog stick into one of the four directions,
+// and a held direction into a move that repeats after a delay.
+
+use crate::game::Direction;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StickQuantizer {
+    // How far the stick has to be pushed to press a direction
+    deadzone: f32,
+    // How far it can fall back before the direction is released
+    release: f32,
+    // Degrees past the diagonal the stick has to turn before a held direction changes
+    hysteresis: f32,
+    held: Option<Direction>,
+}
+
+impl Default for StickQuantizer {
+    fn default() -> Self {
+        Self::new(0.5, 0.35, 15.0)
+    }
+}
+
+impl StickQuantizer {
+    pub fn new(deadzone: f32, release: f32, hysteresis: f32) -> Self {
+        Self {
+            deadzone,
+            release: release.min(deadzone),
+            hysteresis: hysteresis.clamp(0.0, 44.0),
+            held: None,
+        }
+    }
+
+    // Direction held with the stick at `x` and `y`, where positive `y` is down
+    pub fn update(&mut self, x: f32, y: f32) -> Option<Direction> {
+        let length = x.hypot(y);
+        let threshold = if self.held.is_some() {
+            self.release
+        } else {
+            self.deadzone
+        };
+        if length < threshold || length == 0.0 {
+            self.held = None;
+            return None;
+        }
+
+        // The held direction sticks as long as the stick is within 45 degrees plus
+        // the hysteresis of it, so noise around a diagonal can't make it flip
+        let limit = (45.0 + self.hysteresis).to_radians().cos();
+        let keep = self.held.filter(|direction| {
+            let (dx, dy) = direction.delta();
+            (dx as f32 * x + dy as f32 * y) / length >= limit
+        });
+        self.held = keep.or_else(|| {
+            Some(if x.abs() >= y.abs() {
+                if x < 0.0 {
+                    Direction::Left
+                } else {
+                    Direction::Right
+                }
+            } else if y < 0.0 {
+                Direction::Up
+            } else {
+                Direction::Down
+            })
+        });
+        self.held
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoRepeat {
+    // Seconds a direction has to be held before it repeats
+    delay: f64,
+    // Seconds between repeats after that
+    interval: f64,
+    held: Option<Direction>,
+    // Seconds until the next repeat
+    timer: f64,
+}
+
+impl Default for AutoRepeat {
+    fn default() -> Self {
+        Self::new(0.17, 0.05)
+    }
+}
+
+impl AutoRepeat {
+    pub fn new(delay: f64, interval: f64) -> Self {
+        Self {
+            delay,
+            interval,
+            held: None,
+            timer: 0.0,
+        }
+    }
+
+    pub fn held(&self) -> Option<Direction> {
+        self.held
+    }
+
+    // Direction to move in this frame, if any. A new direction moves straight away.
+    // Repeats only happen when `ready` is set; a repeat that comes due before that
+    // waits for it, so a held direction never moves faster than the player can.
+    pub fn update(&mut self, held: Option<Direction>, dt: f64, ready: bool) -> Option<Direction> {
+        if held != self.held {
+            self.held = held;
+            self.timer = self.delay;
+            return held;
+        }
+        let direction = self.held?;
+        self.timer -= dt;
+        if self.timer > 0.0 || !ready {
+            return None;
+        }
+        self.timer = self.interval;
+        Some(direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stick pushed `length` at `degrees` clockwise from right, as positive y is down
+    fn push(stick: &mut StickQuantizer, degrees: f32, length: f32) -> Option<Direction> {
+        let (y, x) = degrees.to_radians().sin_cos();
+        stick.update(x * length, y * length)
+    }
+
+    #[test]
+    fn stick_presses_past_the_deadzone_and_releases_below_the_release() {
+        let mut stick = StickQuantizer::new(0.5, 0.35, 15.0);
+        assert_eq!(push(&mut stick, 0.0, 0.45), None);
+        assert_eq!(push(&mut stick, 0.0, 0.55), Some(Direction::Right));
+        assert_eq!(push(&mut stick, 0.0, 0.4), Some(Direction::Right));
+        assert_eq!(push(&mut stick, 0.0, 0.3), None);
+        // Once released it takes the deadzone again
+        assert_eq!(push(&mut stick, 0.0, 0.4), None);
+        assert_eq!(push(&mut stick, 180.0, 1.0), Some(Direction::Left));
+        assert_eq!(stick.update(0.0, 0.0), None);
+    }
+
+    #[test]
+    fn stick_holds_its_direction_past_the_diagonal() {
+        let mut stick = StickQuantizer::new(0.5, 0.35, 15.0);
+        assert_eq!(push(&mut stick, 0.0, 1.0), Some(Direction::Right));
+        // Noise around the diagonal does not flip it
+        for degrees in [44.0, 50.0, 40.0, 58.0] {
+            assert_eq!(push(&mut stick, degrees, 1.0), Some(Direction::Right));
+        }
+        assert_eq!(push(&mut stick, 62.0, 1.0), Some(Direction::Down));
+        assert_eq!(push(&mut stick, 40.0, 1.0), Some(Direction::Down));
+        assert_eq!(push(&mut stick, 28.0, 1.0), Some(Direction::Right));
+    }
+
+    #[test]
+    fn stick_picks_the_nearest_direction_when_pressed() {
+        for (degrees, direction) in [
+            (30.0, Direction::Right),
+            (60.0, Direction::Down),
+            (-60.0, Direction::Up),
+            (150.0, Direction::Left),
+        ] {
+            let mut stick = StickQuantizer::default();
+            assert_eq!(push(&mut stick, degrees, 1.0), Some(direction));
+        }
+    }
+
+    #[test]
+    fn repeats_after_the_delay_then_every_interval() {
+        let mut repeat = AutoRepeat::new(0.25, 0.125);
+        let left = Some(Direction::Left);
+        assert_eq!(repeat.update(left, 0.0625, true), left);
+        assert_eq!(repeat.update(left, 0.125, true), None);
+        assert_eq!(repeat.update(left, 0.125, true), left);
+        assert_eq!(repeat.update(left, 0.0625, true), None);
+        assert_eq!(repeat.update(left, 0.0625, true), left);
+        assert_eq!(repeat.held(), left);
+    }
+
+    #[test]
+    fn repeat_waits_for_the_player() {
+        let mut repeat = AutoRepeat::new(0.25, 0.125);
+        let left = Some(Direction::Left);
+        assert_eq!(repeat.update(left, 0.0, true), left);
+        assert_eq!(repeat.update(left, 0.5, false), None);
+        assert_eq!(repeat.update(left, 0.0, false), None);
+        assert_eq!(repeat.update(left, 0.0, true), left);
+        // The interval starts over from the repeat, not from when it came due
+        assert_eq!(repeat.update(left, 0.0625, true), None);
+    }
+
+    #[test]
+    fn new_direction_moves_straight_away() {
+        let mut repeat = AutoRepeat::new(0.25, 0.125);
+        let (left, up) = (Some(Direction::Left), Some(Direction::Up));
+        assert_eq!(repeat.update(left, 0.0, true), left);
+        // Even while the player is busy
+        assert_eq!(repeat.update(up, 0.0, false), up);
+        assert_eq!(repeat.update(None, 0.0, true), None);
+        assert_eq!(repeat.update(None, 1.0, true), None);
+        assert_eq!(repeat.update(up, 0.0, true), up);
+        assert_eq!(repeat.update(up, 0.125, true), None);
+    }
+}
@@ -0,0 +1,171 @@
+// Local high score table.
+//
+// Saved as text: a header line with the format version, then one tab separated
+// entry per line. The name goes last so it is the only free-form field.
+
+use crate::versioned::{read_versioned, write_header, FormatError};
+
+pub const MAX_ENTRIES: usize = 10;
+// Longest name that can be entered
+pub const MAX_NAME_LENGTH: usize = 12;
+
+const HEADER: &str = "GOOP HIGH SCORES";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighScoreEntry {
+    pub name: String,
+    pub score: u64,
+    pub goops: u32,
+    pub level: u32,
+    // Local date the run ended on, as YYYY-MM-DD
+    pub date: String,
+    pub seed: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HighScoreTable {
+    // Sorted from highest to lowest score
+    entries: Vec<HighScoreEntry>,
+}
+
+impl HighScoreTable {
+    pub fn entries(&self) -> &[HighScoreEntry] {
+        &self.entries
+    }
+
+    // True if a run with `score` would make it onto the table
+    pub fn qualifies(&self, score: u64) -> bool {
+        score > 0
+            && (self.entries.len() < MAX_ENTRIES
+                || self.entries.last().is_some_and(|entry| score > entry.score))
+    }
+
+    // Adds an entry and returns its rank starting at 0, or `None` if it did not qualify
+    pub fn insert(&mut self, mut entry: HighScoreEntry) -> Option<usize> {
+        if !self.qualifies(entry.score) {
+            return None;
+        }
+
+        entry.name = sanitize_name(&entry.name);
+        // Earlier entries win ties
+        let rank = self
+            .entries
+            .iter()
+            .position(|other| entry.score > other.score)
+            .unwrap_or(self.entries.len());
+        self.entries.insert(rank, entry);
+        self.entries.truncate(MAX_ENTRIES);
+        Some(rank)
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = write_header(HEADER, VERSION);
+        for entry in &self.entries {
+            text += &format!(
+                "{}\t{}\t{}\t{}\t{}\t{}\n",
+                entry.score, entry.goops, entry.level, entry.seed, entry.date, entry.name
+            );
+        }
+        text
+    }
+
+    // Lines that cannot be read are skipped, so a damaged file loses only those entries
+    pub fn decode(text: &str) -> Result<Self, FormatError> {
+        let (version, lines) = read_versioned(text, HEADER)?;
+        let parse: fn(&str) -> Option<HighScoreEntry> = match version {
+            1 => parse_entry_v1,
+            _ => return Err(FormatError::UnsupportedVersion(version.to_string())),
+        };
+
+        let mut table = Self::default();
+        for entry in lines.filter_map(parse) {
+            table.insert(entry);
+        }
+        Ok(table)
+    }
+}
+
+fn parse_entry_v1(line: &str) -> Option<HighScoreEntry> {
+    let mut fields = line.splitn(6, '\t');
+    Some(HighScoreEntry {
+        score: fields.next()?.parse().ok()?,
+        goops: fields.next()?.parse().ok()?,
+        level: fields.next()?.parse().ok()?,
+        seed: fields.next()?.parse().ok()?,
+        date: fields.next()?.to_string(),
+        name: fields.next()?.to_string(),
+    })
+}
+
+// Keeps names on a single line and within the length limit
+fn sanitize_name(name: &str) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_NAME_LENGTH)
+        .collect();
+
+    if name.is_empty() {
+        "???".to_string()
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(name: &str, score: u64) -> HighScoreEntry {
+        HighScoreEntry {
+            name: name.to_string(),
+            score,
+            goops: 3,
+            level: 2,
+            date: "2024-05-01".to_string(),
+            seed: -7,
+        }
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut table = HighScoreTable::default();
+        table.insert(entry("ALICE", 500));
+        table.insert(entry("BOB\tTHE\nBUILDER", 900));
+        assert_eq!(HighScoreTable::decode(&table.encode()), Ok(table));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        for text in ["", "garbage", "\u{0}\u{1}\n1\t2", "GOOP HIGH SCORESX 1\n"] {
+            assert_eq!(
+                HighScoreTable::decode(text),
+                Err(FormatError::MissingHeader),
+                "{text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let text = write_header(HEADER, VERSION + 1) + "100\t1\t1\t0\t2024-05-01\tALICE\n";
+        assert_eq!(
+            HighScoreTable::decode(&text),
+            Err(FormatError::UnsupportedVersion((VERSION + 1).to_string()))
+        );
+    }
+
+    #[test]
+    fn skips_bad_lines() {
+        let text = write_header(HEADER, VERSION)
+            + "100\t3\t2\t-7\t2024-05-01\tALICE\n"
+            + "lots of points\t3\t2\t-7\t2024-05-01\tMALLORY\n"
+            + "300\t3\n"
+            + "\n"
+            + "200\t3\t2\t-7\t2024-05-01\tBOB\n";
+        let table = HighScoreTable::decode(&text).unwrap();
+        assert_eq!(table.entries(), [entry("BOB", 200), entry("ALICE", 100)]);
+    }
+}
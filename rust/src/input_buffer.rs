@@ -0,0 +1,119 @@
+// Commands given while the player is still busy with the last one. They are kept
+// for a short while and run in order as soon as the player is free again.
+
+use crate::game::Command;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputBuffer {
+    // Most commands waiting at once. Commands given while it is full are dropped.
+    capacity: usize,
+    // Seconds a command waits before it is dropped
+    expiry: f64,
+    // Commands with the time they were given
+    queue: VecDeque<(f64, Command)>,
+}
+
+impl Default for InputBuffer {
+    fn default() -> Self {
+        Self::new(3, 0.25)
+    }
+}
+
+impl InputBuffer {
+    pub fn new(capacity: usize, expiry: f64) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            expiry,
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn push(&mut self, now: f64, command: Command) {
+        self.expire(now);
+        if self.queue.len() < self.capacity {
+            self.queue.push_back((now, command));
+        }
+    }
+
+    // The next command to run, left in the buffer
+    pub fn peek(&mut self, now: f64) -> Option<Command> {
+        self.expire(now);
+        self.queue.front().map(|&(_, command)| command)
+    }
+
+    pub fn pop(&mut self, now: f64) -> Option<Command> {
+        self.expire(now);
+        self.queue.pop_front().map(|(_, command)| command)
+    }
+
+    fn expire(&mut self, now: f64) {
+        while self
+            .queue
+            .front()
+            .is_some_and(|&(time, _)| now - time > self.expiry)
+        {
+            self.queue.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Direction;
+
+    const LEFT: Command = Command::Move(Direction::Left);
+    const UP: Command = Command::Move(Direction::Up);
+
+    #[test]
+    fn runs_commands_in_order() {
+        let mut buffer = InputBuffer::new(3, 0.25);
+        buffer.push(0.0, LEFT);
+        buffer.push(0.1, Command::Shoot);
+        assert_eq!(buffer.peek(0.2), Some(LEFT));
+        assert_eq!(buffer.pop(0.2), Some(LEFT));
+        assert_eq!(buffer.pop(0.2), Some(Command::Shoot));
+        assert_eq!(buffer.pop(0.2), None);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn drops_commands_that_waited_too_long() {
+        let mut buffer = InputBuffer::new(3, 0.25);
+        buffer.push(0.0, LEFT);
+        buffer.push(0.2, UP);
+        // Exactly at the expiry the command still counts
+        assert_eq!(buffer.peek(0.25), Some(LEFT));
+        assert_eq!(buffer.peek(0.3), Some(UP));
+        assert_eq!(buffer.pop(0.5), None);
+    }
+
+    #[test]
+    fn drops_commands_given_while_full() {
+        let mut buffer = InputBuffer::new(2, 0.25);
+        buffer.push(0.0, LEFT);
+        buffer.push(0.0, UP);
+        buffer.push(0.0, Command::Shoot);
+        assert_eq!(buffer.pop(0.0), Some(LEFT));
+        assert_eq!(buffer.pop(0.0), Some(UP));
+        assert_eq!(buffer.pop(0.0), None);
+
+        // Expired commands make room before a new one is counted
+        buffer.push(0.0, LEFT);
+        buffer.push(0.0, UP);
+        buffer.push(0.3, Command::Shoot);
+        assert_eq!(buffer.pop(0.3), Some(Command::Shoot));
+    }
+
+    #[test]
+    fn keeps_at_least_one_command() {
+        let mut buffer = InputBuffer::new(0, 0.25);
+        buffer.push(0.0, LEFT);
+        assert_eq!(buffer.pop(0.0), Some(LEFT));
+    }
+}
@@ -0,0 +1,159 @@
+// Keys and gamepad buttons the player has bound to each action.
+//
+// Saved as text: a header line with the format version, then one tab separated
+// line per action: its name followed by its bindings, each `key:<code>` or
+// `button:<code>`. An action without bindings is saved as just its name.
+
+use crate::versioned::{read_versioned, write_header, FormatError};
+use std::fmt;
+
+// Actions the player can rebind, in the order the controls screen lists them
+pub const REBINDABLE_ACTIONS: [&str; 5] = ["left", "right", "up", "down", "shoot"];
+// Kept in the profile so their bindings count as taken, but not rebindable
+pub const RESERVED_ACTIONS: [&str; 2] = ["restart", "pause"];
+pub const MAX_BINDINGS: usize = 4;
+
+const HEADER: &str = "GOOP INPUT PROFILE";
+const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    // Physical keycode, so the keys stay in place on other keyboard layouts
+    Key(i32),
+    // Gamepad button index
+    Button(i32),
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Key(code) => write!(f, "key:{code}"),
+            Self::Button(code) => write!(f, "button:{code}"),
+        }
+    }
+}
+
+impl Binding {
+    fn parse(text: &str) -> Option<Self> {
+        let (device, code) = text.split_once(':')?;
+        let code = code.parse().ok()?;
+        match device {
+            "key" => Some(Self::Key(code)),
+            "button" => Some(Self::Button(code)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindingError {
+    UnknownAction(String),
+    // The binding is already bound to the given action
+    Conflict(String),
+    TooManyBindings,
+}
+
+impl fmt::Display for BindingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownAction(action) => write!(f, "unknown action {action}"),
+            Self::Conflict(action) => write!(f, "already bound to {action}"),
+            Self::TooManyBindings => write!(f, "at most {MAX_BINDINGS} bindings per action"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputProfile {
+    // Actions in the order they were added, each with its bindings
+    actions: Vec<(String, Vec<Binding>)>,
+}
+
+impl InputProfile {
+    pub fn actions(&self) -> impl Iterator<Item = (&str, &[Binding])> {
+        self.actions
+            .iter()
+            .map(|(action, bindings)| (action.as_str(), bindings.as_slice()))
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions
+            .iter()
+            .find(|(name, _)| name == action)
+            .map_or(&[], |(_, bindings)| bindings.as_slice())
+    }
+
+    // Action `binding` belongs to, if any
+    pub fn action_for(&self, binding: Binding) -> Option<&str> {
+        self.actions
+            .iter()
+            .find(|(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| action.as_str())
+    }
+
+    // Adds `action` with no bindings, if it isn't in the profile yet
+    pub fn add_action(&mut self, action: &str) {
+        if !self.actions.iter().any(|(name, _)| name == action) {
+            self.actions.push((action.to_string(), Vec::new()));
+        }
+    }
+
+    // Adds `binding` to `action`, unless some action already has it
+    pub fn bind(&mut self, action: &str, binding: Binding) -> Result<(), BindingError> {
+        if let Some(owner) = self.action_for(binding) {
+            return Err(BindingError::Conflict(owner.to_string()));
+        }
+        let bindings = self.bindings_mut(action)?;
+        if bindings.len() >= MAX_BINDINGS {
+            return Err(BindingError::TooManyBindings);
+        }
+        bindings.push(binding);
+        Ok(())
+    }
+
+    pub fn clear(&mut self, action: &str) -> Result<(), BindingError> {
+        self.bindings_mut(action)?.clear();
+        Ok(())
+    }
+
+    fn bindings_mut(&mut self, action: &str) -> Result<&mut Vec<Binding>, BindingError> {
+        self.actions
+            .iter_mut()
+            .find(|(name, _)| name == action)
+            .map(|(_, bindings)| bindings)
+            .ok_or_else(|| BindingError::UnknownAction(action.to_string()))
+    }
+
+    pub fn encode(&self) -> String {
+        let mut text = write_header(HEADER, VERSION);
+        for (action, bindings) in &self.actions {
+            text += action;
+            for binding in bindings {
+                text += &format!("\t{binding}");
+            }
+            text += "\n";
+        }
+        text
+    }
+
+    // Bindings that cannot be read, or that clash with an earlier one, are skipped
+    pub fn decode(text: &str) -> Result<Self, FormatError> {
+        let (version, lines) = read_versioned(text, HEADER)?;
+        if version != VERSION {
+            return Err(FormatError::UnsupportedVersion(version.to_string()));
+        }
+
+        let mut profile = Self::default();
+        for line in lines {
+            let mut fields = line.split('\t');
+            let Some(action) = fields.next().filter(|action| !action.is_empty()) else {
+                continue;
+            };
+            profile.add_action(action);
+            for binding in fields.filter_map(Binding::parse) {
+                let _ = profile.bind(action, binding);
+            }
+        }
+        Ok(profile)
+    }
+}
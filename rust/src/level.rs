@@ -0,0 +1,112 @@
+// Level progression. Each level has a quota of enemies to kill before the board
+// is cleared and the next, faster level begins.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelSpec {
+    // Enemies that have to be killed to complete the level
+    pub quota: u16,
+    // Seconds between spawns
+    pub spawn_interval: f64,
+    // How many colors of the palette enemies can spawn with, counting from the first
+    pub colors: usize,
+}
+
+// Levels past the end of the table keep using the last entry
+const LEVELS: [LevelSpec; 9] = [
+    LevelSpec {
+        quota: 20,
+        spawn_interval: 1.0,
+        colors: 3,
+    },
+    LevelSpec {
+        quota: 25,
+        spawn_interval: 0.9,
+        colors: 3,
+    },
+    LevelSpec {
+        quota: 30,
+        spawn_interval: 0.85,
+        colors: 4,
+    },
+    LevelSpec {
+        quota: 35,
+        spawn_interval: 0.75,
+        colors: 4,
+    },
+    LevelSpec {
+        quota: 40,
+        spawn_interval: 0.65,
+        colors: 4,
+    },
+    LevelSpec {
+        quota: 45,
+        spawn_interval: 0.55,
+        colors: 5,
+    },
+    LevelSpec {
+        quota: 50,
+        spawn_interval: 0.5,
+        colors: 5,
+    },
+    LevelSpec {
+        quota: 55,
+        spawn_interval: 0.45,
+        colors: 6,
+    },
+    LevelSpec {
+        quota: 60,
+        spawn_interval: 0.4,
+        colors: 6,
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Level {
+    // Starts at 1
+    number: u32,
+    kills: u16,
+}
+
+impl Default for Level {
+    fn default() -> Self {
+        Self {
+            number: 1,
+            kills: 0,
+        }
+    }
+}
+
+impl Level {
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn spec(&self) -> &'static LevelSpec {
+        let index = (self.number as usize - 1).min(LEVELS.len() - 1);
+        &LEVELS[index]
+    }
+
+    // Kills still needed to complete the level
+    pub fn remaining(&self) -> u16 {
+        self.spec().quota.saturating_sub(self.kills)
+    }
+
+    // Counts kills towards the quota. Returns true once the quota is met.
+    pub fn add_kills(&mut self, kills: u16) -> bool {
+        self.kills = self.kills.saturating_add(kills);
+        self.remaining() == 0
+    }
+
+    pub fn advance(&mut self) {
+        self.number += 1;
+        self.kills = 0;
+    }
+}
+
+// How far `spawn_interval` has come from the first level's towards the last level's,
+// from 0 to 1
+pub fn pace(spawn_interval: f64) -> f64 {
+    let slowest = LEVELS[0].spawn_interval;
+    let fastest = LEVELS[LEVELS.len() - 1].spawn_interval;
+    ((slowest - spawn_interval) / (slowest - fastest)).clamp(0.0, 1.0)
+}
@@ -1,486 +1,2171 @@
-use godot::classes::{ISprite2D, ITileMap, Label, Sprite2D, TileMap, Timer};
-use godot::global::instance_from_id;
+use audio::*;
+use board::*;
+use bot::*;
+use game::*;
+use godot::classes::control::SizeFlags;
+use godot::classes::file_access::ModeFlags;
+use godot::classes::node::ProcessMode;
+use godot::classes::{
+    AudioServer, AudioStream, AudioStreamPlayer, Button, CanvasItem, Control, DirAccess,
+    FileAccess, Font, HBoxContainer, IControl, ILabel, INode, IPanelContainer, ISprite2D, ITileMap,
+    InputEvent, InputEventJoypadButton, InputEventKey, InputEventScreenTouch, InputMap, Label,
+    LineEdit, PanelContainer, Range, Resource, Sprite2D, TileMap, Time, Timer, Tween,
+    VBoxContainer, OS,
+};
+use godot::global::{JoyAxis, JoyButton, Key};
 use godot::prelude::*;
-use rand::distributions::Standard;
+use held_direction::{AutoRepeat, StickQuantizer};
+use high_scores::*;
+use input_buffer::InputBuffer;
+use input_profile::*;
+use music::{stem_volume, Intensity};
+use palette::{Color, Palette, PaletteError};
+use player_motion::*;
 use rand::prelude::*;
-use std::collections::HashMap;
+use rand_chacha::ChaCha8Rng;
+use replay::*;
+use scoring::*;
+use std::collections::{BTreeMap, VecDeque};
+use touch::{Gesture, TouchInput, TouchTracker};
+
+mod audio;
+mod board;
+mod bot;
+mod game;
+mod held_direction;
+mod high_scores;
+mod input_buffer;
+mod input_profile;
+mod level;
+mod music;
+mod palette;
+mod player_motion;
+mod registry;
+mod replay;
+mod scoring;
+mod touch;
+mod versioned;
+
+// Where the inputs of the last finished run are saved
+const LAST_REPLAY_PATH: &str = "user://last_run.replay";
+const HIGH_SCORES_PATH: &str = "user://high_scores.txt";
+const INPUT_PROFILE_PATH: &str = "user://input_profile.txt";
+const AUDIO_SETTINGS_PATH: &str = "user://audio.txt";
+const GAME_SCENE: &str = "res://root.tscn";
+const MENU_SCENE: &str = "res://menu.tscn";
+const CONTROLS_SCENE: &str = "res://controls.tscn";
+const ENEMY_SCENE: &str = "res://enemy.tscn";
+// Seconds between clearing a level and the first spawn of the next one
+const LEVEL_TRANSITION_TIME: f64 = 2.0;
+// Audio buses from `default_bus_layout.tres`
+const SFX_BUS: &str = "SFX";
+const MUSIC_BUS: &str = "Music";
+// Seconds between the notes of a chain of kills
+const CHAIN_NOTE_GAP: f64 = 0.06;
 
 struct GoopExtension;
 
 #[gdextension]
 unsafe impl ExtensionLibrary for GoopExtension {}
 
-const GRID_WIDTH: usize = 18;
-const GRID_HEIGHT: usize = 12;
-const CENTER_SIZE: usize = 4;
-// X coordinate of center ranges from 7-10
-const MIN_CENTER_X: usize = GRID_WIDTH / 2 - CENTER_SIZE / 2;
-const MAX_CENTER_X: usize = GRID_WIDTH / 2 + CENTER_SIZE / 2 - 1;
-// X coordinate of center ranges from 4-7
-const MIN_CENTER_Y: usize = GRID_HEIGHT / 2 - CENTER_SIZE / 2;
-const MAX_CENTER_Y: usize = GRID_HEIGHT / 2 + CENTER_SIZE / 2 - 1;
+// Size of a cell in the spritesheet and tileset
+const SPRITE_SIZE: f32 = 16.0;
 
-type EnemyId = usize;
+impl BoardConfig {
+    // Converts field position to screen coords
+    fn to_vector(&self, position: Position) -> Vector2 {
+        let half = self.cell_size / 2.0;
+        Vector2::new(
+            position.x as f32 * self.cell_size + half,
+            position.y as f32 * self.cell_size + half,
+        )
+    }
+
+    // Cell under screen coords, if they are on the board
+    fn cell_at(&self, vector: Vector2) -> Option<Position> {
+        if vector.x < 0.0 || vector.y < 0.0 {
+            return None;
+        }
+        let x = (vector.x / self.cell_size) as usize;
+        let y = (vector.y / self.cell_size) as usize;
+        (x < self.width && y < self.height).then_some(Position { x, y })
+    }
+
+    // Scale that makes a sprite fill one cell
+    fn sprite_scale(&self) -> Vector2 {
+        Vector2::ONE * (self.cell_size / SPRITE_SIZE)
+    }
+}
+
+// Where a color is drawn in the spritesheet
+#[derive(Debug, Clone, Copy)]
+struct ColorSprite {
+    player: Rect2,
+    enemy: Rect2,
+}
+
+// Where each power-up is drawn in the spritesheet. They look the same whatever their color.
+#[derive(Debug, Clone, Copy)]
+struct PowerUpSprites {
+    bomb: Rect2,
+    lightning: Rect2,
+    color_wash: Rect2,
+}
+
+// One column per power-up, in the row below the enemies
+impl Default for PowerUpSprites {
+    fn default() -> Self {
+        let region = |column: f32| {
+            Rect2::new(
+                Vector2::new(column, 3.0) * SPRITE_SIZE,
+                Vector2::ONE * SPRITE_SIZE,
+            )
+        };
+        Self {
+            bomb: region(0.0),
+            lightning: region(1.0),
+            color_wash: region(2.0),
+        }
+    }
+}
+
+// Sprites of every palette color, indexed by color, and of the power-ups
+#[derive(Debug, Clone)]
+struct SpriteTable {
+    colors: Vec<ColorSprite>,
+    power_ups: PowerUpSprites,
+}
+
+// Matches the default palette, one column per color
+impl Default for SpriteTable {
+    fn default() -> Self {
+        let size = Vector2::ONE * SPRITE_SIZE;
+        let colors = (0..Palette::default().color_count())
+            .map(|index| {
+                let x = index as f32 * SPRITE_SIZE;
+                ColorSprite {
+                    player: Rect2::new(Vector2::new(x, 16.0), size),
+                    enemy: Rect2::new(Vector2::new(x, 32.0), size),
+                }
+            })
+            .collect();
+        Self {
+            colors,
+            power_ups: PowerUpSprites::default(),
+        }
+    }
+}
+
+impl SpriteTable {
+    fn color_count(&self) -> usize {
+        self.colors.len()
+    }
+
+    fn get(&self, color: Color) -> ColorSprite {
+        self.colors[color.0 as usize]
+    }
+
+    // Region an enemy of `kind` and `color` is drawn with. Power-ups have their own
+    // sprite in place of the colored one.
+    fn enemy(&self, color: Color, kind: PieceKind) -> Rect2 {
+        match kind {
+            PieceKind::Normal => self.get(color).enemy,
+            PieceKind::Bomb => self.power_ups.bomb,
+            PieceKind::Lightning => self.power_ups.lightning,
+            PieceKind::ColorWash => self.power_ups.color_wash,
+        }
+    }
+}
+
+// A color that enemies and the player can have
+#[derive(GodotClass)]
+#[class(init, base=Resource)]
+struct PaletteColor {
+    #[export]
+    name: GString,
+    // How likely the color is to spawn compared to the others
+    #[export]
+    #[init(default = 1)]
+    weight: i64,
+    // Regions of the spritesheet the player and enemies are drawn with
+    #[export]
+    player_region: Rect2,
+    #[export]
+    enemy_region: Rect2,
+    base: Base<Resource>,
+}
+
+#[derive(GodotClass)]
+#[class(init, base=Resource)]
+struct ColorPalette {
+    // Levels introduce colors in this order
+    #[export]
+    colors: Array<Gd<PaletteColor>>,
+    // Regions of the spritesheet the power-ups are drawn with
+    #[export]
+    #[init(default = PowerUpSprites::default().bomb)]
+    bomb_region: Rect2,
+    #[export]
+    #[init(default = PowerUpSprites::default().lightning)]
+    lightning_region: Rect2,
+    #[export]
+    #[init(default = PowerUpSprites::default().color_wash)]
+    color_wash_region: Rect2,
+    base: Base<Resource>,
+}
+
+impl ColorPalette {
+    // Splits the palette into the spawn weights the rules use and the sprites the views use
+    fn load(&self) -> Result<(Palette, SpriteTable), PaletteError> {
+        let colors: Vec<_> = self.colors.iter_shared().collect();
+        let weights = colors
+            .iter()
+            .map(|color| color.bind().weight.clamp(0, u32::MAX as i64) as u32)
+            .collect();
+        let sprites = SpriteTable {
+            colors: colors
+                .iter()
+                .map(|color| {
+                    let color = color.bind();
+                    ColorSprite {
+                        player: color.player_region,
+                        enemy: color.enemy_region,
+                    }
+                })
+                .collect(),
+            power_ups: PowerUpSprites {
+                bomb: self.bomb_region,
+                lightning: self.lightning_region,
+                color_wash: self.color_wash_region,
+            },
+        };
+        Ok((Palette::new(weights)?, sprites))
+    }
+}
 
 #[derive(Debug, Clone, Copy, Default)]
-struct Position {
-    x: usize,
-    y: usize,
+struct PoolStats {
+    // Enemies instantiated from the scene
+    created: u32,
+    // Spawns that got an idle enemy instead of a new one
+    reused: u32,
+    // Removed enemies that went back to the pool
+    recycled: u32,
+    // Removed enemies that were freed because the pool was full
+    freed: u32,
 }
 
-impl Position {
-    // Converts field position to screen coords
-    fn to_vector(&self) -> Vector2 {
-        Vector2::new(self.x as f32 * 16.0 + 8.0, self.y as f32 * 16.0 + 8.0)
+// Removed enemies are hidden and kept here so later spawns can reuse them
+#[derive(Default)]
+struct EnemyPool {
+    scene: Option<Gd<PackedScene>>,
+    idle: Vec<Gd<Enemy>>,
+    // Most idle enemies kept around
+    capacity: usize,
+    stats: PoolStats,
+}
+
+impl EnemyPool {
+    fn new(scene: Gd<PackedScene>, capacity: usize) -> Self {
+        Self {
+            scene: Some(scene),
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    // Fills the pool up front so the first spawns do not instantiate anything
+    fn prewarm(&mut self, layer: &mut Gd<Node>) {
+        while self.idle.len() < self.capacity {
+            let mut enemy = self.create(layer);
+            enemy.hide();
+            self.idle.push(enemy);
+        }
+    }
+
+    fn acquire(&mut self, layer: &mut Gd<Node>) -> Gd<Enemy> {
+        match self.idle.pop() {
+            Some(mut enemy) => {
+                self.stats.reused += 1;
+                enemy.show();
+                enemy
+            }
+            None => self.create(layer),
+        }
+    }
+
+    fn release(&mut self, mut enemy: Gd<Enemy>) {
+        enemy.bind_mut().stop();
+        if self.idle.len() < self.capacity {
+            self.stats.recycled += 1;
+            enemy.hide();
+            self.idle.push(enemy);
+        } else {
+            self.stats.freed += 1;
+            enemy.queue_free();
+        }
+    }
+
+    fn create(&mut self, layer: &mut Gd<Node>) -> Gd<Enemy> {
+        let scene = self.scene.as_ref().expect("pool is set up in ready");
+        let enemy: Gd<Enemy> = scene.instantiate().unwrap().cast();
+        layer.add_child(enemy.clone());
+        self.stats.created += 1;
+        enemy
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
-enum Direction {
-    #[default]
-    Left,
-    Right,
-    Up,
-    Down,
+// Enemy nodes under the ids the game state uses for the enemies they show. Ordered
+// by id so the consistency check lists left over nodes the same way every frame.
+#[derive(Default)]
+struct EnemyNodes(BTreeMap<EnemyId, Gd<Enemy>>);
+
+impl EnemyNodes {
+    fn insert(&mut self, enemy_id: EnemyId, enemy: Gd<Enemy>) {
+        self.0.insert(enemy_id, enemy);
+    }
+
+    // `None` if the enemy is gone or its node was freed by something else
+    fn get(&self, enemy_id: EnemyId) -> Option<Gd<Enemy>> {
+        self.0
+            .get(&enemy_id)
+            .filter(|enemy| enemy.is_instance_valid())
+            .cloned()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (EnemyId, &Gd<Enemy>)> {
+        self.0.iter().map(|(&enemy_id, enemy)| (enemy_id, enemy))
+    }
+
+    fn remove(&mut self, enemy_id: EnemyId) -> Option<Gd<Enemy>> {
+        self.0
+            .remove(&enemy_id)
+            .filter(|enemy| enemy.is_instance_valid())
+    }
+
+    // Nodes of the enemies in `lane`, nearest to the center first
+    fn in_lane(&self, state: &GameState, lane: Lane) -> Vec<Gd<Enemy>> {
+        lane_enemies(state, lane)
+            .into_iter()
+            .filter_map(|(enemy_id, _)| self.get(enemy_id))
+            .collect()
+    }
 }
 
-impl Direction {
-    fn opposite(&self) -> Self {
-        match self {
-            Self::Left => Self::Right,
-            Self::Right => Self::Left,
-            Self::Up => Self::Down,
-            Self::Down => Self::Up,
+#[derive(GodotClass)]
+#[class(init, base=TileMap)]
+struct Field {
+    // Seed for every random choice in a run
+    // If left at 0, a random seed is picked when the field is ready
+    #[export]
+    #[var(get = get_seed)]
+    seed: i64,
+    // Replay file to play back instead of reading player input
+    // If set, the seed stored in the replay is used
+    #[export]
+    replay_path: GString,
+    // Points for the first kill of a shot. Replays use the scoring they were recorded with.
+    #[export]
+    #[init(default = 100)]
+    base_points: i64,
+    // How the points for each further kill in the same shot grow
+    #[export(enum = (Linear, Triangular, Exponential))]
+    #[init(default = 1)]
+    chain_bonus: i64,
+    // Size of the field in cells. Replays use the size they were recorded with.
+    #[export]
+    #[init(default = 18)]
+    board_width: i64,
+    #[export]
+    #[init(default = 12)]
+    board_height: i64,
+    // Size of the square the player moves around in
+    #[export]
+    #[init(default = 4)]
+    center_size: i64,
+    // Size of a cell on screen in pixels
+    #[export]
+    #[init(default = 16.0)]
+    cell_size: f32,
+    // Colors pieces can have. The default palette is used if left empty.
+    // Replays keep the weights they were recorded with, only the sprites come from here.
+    #[export]
+    palette: Option<Gd<ColorPalette>>,
+    // Node enemies and score popups are added to. Defaults to the field's parent.
+    #[export]
+    enemy_layer: Option<Gd<Node>>,
+    // Timer whose timeouts spawn enemies. Without it the field disables itself.
+    #[export]
+    spawn_timer: Option<Gd<Timer>>,
+    // Enemies kept around for reuse once they leave the board
+    #[export]
+    #[init(default = 64)]
+    pool_size: i64,
+    pool: EnemyPool,
+    sprites: SpriteTable,
+    #[init(default = ChaCha8Rng::seed_from_u64(0))]
+    rng: ChaCha8Rng,
+    state: GameState,
+    // Frames since the field was ready, used to timestamp replay inputs
+    tick: u64,
+    // Seconds the current run has lasted
+    elapsed: f64,
+    replay: Replay,
+    playback: Option<Playback>,
+    // Nodes showing the enemies in the game state
+    enemies: EnemyNodes,
+    // True from clearing a level until the next one starts
+    between_levels: bool,
+    // Problems the consistency check found last frame, so each is only reported once
+    inconsistencies: Vec<String>,
+    base: Base<TileMap>,
+}
+
+#[godot_api]
+impl ITileMap for Field {
+    fn ready(&mut self) {
+        if self.spawn_timer.is_none() {
+            godot_error!("Field has no spawn timer, disabling it");
+            self.base_mut().set_process_mode(ProcessMode::DISABLED);
+            return;
+        }
+        if self.enemy_layer.is_none() {
+            self.enemy_layer = self.base().get_parent();
+        }
+        if self.enemy_layer.is_none() {
+            godot_error!("Field has no enemy layer to add enemies to, disabling it");
+            self.spawn_timer().stop();
+            self.base_mut().set_process_mode(ProcessMode::DISABLED);
+            return;
+        }
+
+        let mut scoring = ScoringModel {
+            base_points: self.base_points.max(0) as u64,
+            chain_bonus: ChainBonus::from_index(self.chain_bonus).unwrap_or_default(),
+        };
+        let mut board = BoardConfig {
+            width: self.board_width.max(0) as usize,
+            height: self.board_height.max(0) as usize,
+            center_size: self.center_size.max(0) as usize,
+            cell_size: self.cell_size,
+        };
+
+        let (mut palette, sprites) = match &self.palette {
+            Some(palette) => palette.bind().load().unwrap_or_else(|err| {
+                godot_error!("Invalid palette, using the default one: {}", err);
+                Default::default()
+            }),
+            None => Default::default(),
+        };
+        self.sprites = sprites;
+
+        if !self.replay_path.is_empty() {
+            let bytes = FileAccess::get_file_as_bytes(self.replay_path.clone());
+            match Replay::decode(bytes.as_slice()) {
+                Ok(replay) if replay.palette.color_count() > self.sprites.color_count() => {
+                    godot_error!(
+                        "Could not play replay {}: it has {} colors, the palette only {}",
+                        self.replay_path,
+                        replay.palette.color_count(),
+                        self.sprites.color_count()
+                    );
+                }
+                Ok(replay) => {
+                    self.seed = replay.seed;
+                    scoring = replay.scoring;
+                    board = BoardConfig {
+                        cell_size: board.cell_size,
+                        ..replay.board
+                    };
+                    palette = replay.palette.clone();
+                    self.playback = Some(Playback::new(replay));
+                    // Spawns come from the replay instead of the timer
+                    self.spawn_timer().stop();
+                }
+                Err(err) => godot_error!("Could not load replay {}: {}", self.replay_path, err),
+            }
+        }
+
+        if self.seed == 0 {
+            self.seed = thread_rng().gen();
+        }
+
+        if let Err(err) = board.validate() {
+            godot_error!("Invalid board, using the default one: {}", err);
+            board = BoardConfig::default();
+        }
+
+        self.rng = ChaCha8Rng::seed_from_u64(self.seed as u64);
+        self.replay = Replay::new(self.seed, board, scoring, palette.clone());
+        self.state = GameState::new(board, palette, &mut self.rng);
+        self.state.set_scoring(scoring);
+
+        self.update_spawn_interval();
+
+        self.pool = EnemyPool::new(load(ENEMY_SCENE), self.pool_size.max(0) as usize);
+        // The enemy layer may still be setting up its children, so fill the pool afterwards
+        self.base_mut().call_deferred("prewarm_pool".into(), &[]);
+
+        // The tileset is drawn at sprite size
+        self.base_mut().set_scale(board.sprite_scale());
+        for x in 0..board.width {
+            for y in 0..board.height {
+                let (i, j) = match (board.x_in_center(x), board.y_in_center(y)) {
+                    // If both are in center, use tile is in center. Use sprite located at (0, 0).
+                    (true, true) => (0, 0),
+                    // If only one is in center, use tile is on edge. Use sprite located at (1, 0).
+                    (true, false) | (false, true) => (1, 0),
+                    // If neither are in center, use tile is in corner. Use sprite located at (2, 0).
+                    (false, false) => (2, 0),
+                };
+
+                self.base_mut()
+                    // first argument is layer
+                    // second argument is position of cell in tilemap
+                    .set_cell_ex(0, Vector2i::new(x as i32, y as i32))
+                    // the ID of the tileset
+                    .source_id(0)
+                    // coordinates of the sprite in the tileset
+                    .atlas_coords(Vector2i::new(i, j))
+                    .done();
+            }
+        }
+    }
+
+    fn process(&mut self, dt: f64) {
+        self.tick += 1;
+        if !self.state.is_lost() {
+            self.elapsed += dt;
+        }
+
+        if cfg!(debug_assertions) {
+            self.check_consistency();
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
-enum Color {
-    #[default]
-    Red,
-    Green,
-    Blue,
-    Purple,
+#[godot_api]
+impl Field {
+    // Emitted once when an enemy reaches the center
+    #[signal]
+    fn game_over(score: i64, goops: i64, duration: f64);
+
+    // Emitted when the quota of `level` is met, before the board moves on to the next level
+    #[signal]
+    fn level_completed(level: u32);
+
+    // Lanes are numbered like `bot::lanes`: rows left of the center, rows right of it,
+    // columns above it, then columns below it. `lane_count` is how many the board has.
+    // Colors are indices into the palette.
+    #[signal]
+    fn enemy_spawned(id: i64, lane: i64, lane_count: i64, color: i64);
+
+    // `chain_index` counts the kills of a single shot, starting at 0
+    #[signal]
+    fn enemy_killed(id: i64, color: i64, chain_index: i64);
+
+    // A shot hit a power-up, before the kills it causes. Kinds are 1 bomb,
+    // 2 lightning and 3 color wash.
+    #[signal]
+    fn power_up_triggered(id: i64, kind: i64, x: i64, y: i64);
+
+    // Emitted whenever the time between spawns changes, including when the run starts
+    #[signal]
+    fn speed_changed(wait_time: f64);
+
+    // Emitted with the new total whenever points are scored
+    #[signal]
+    fn score_changed(points: i64);
+
+    // The seed the current run was started with, for reporting and replaying it
+    #[func]
+    fn get_seed(&self) -> i64 {
+        self.seed
+    }
+
+    // Writes the inputs of the current run to `path`
+    #[func]
+    fn save_replay(&self, path: GString) -> bool {
+        let Some(mut file) = FileAccess::open(path.clone(), ModeFlags::WRITE) else {
+            godot_error!("Could not write replay {}", path);
+            return false;
+        };
+        file.store_buffer(PackedByteArray::from(self.replay.encode().as_slice()));
+        true
+    }
+
+    // Starts spawning at the speed of the current level
+    #[func]
+    fn start_level(&mut self) {
+        self.between_levels = false;
+        if self.state.is_lost() || self.is_replaying() {
+            return;
+        }
+
+        self.update_spawn_interval();
+        self.spawn_timer().start();
+    }
+
+    #[func]
+    fn recycle_enemy(&mut self, enemy: Gd<Enemy>) {
+        self.pool.release(enemy);
+    }
+
+    #[func]
+    fn prewarm_pool(&mut self) {
+        let mut layer = self.enemy_layer();
+        self.pool.prewarm(&mut layer);
+    }
+
+    // Enemies in a lane, nearest to the center first. Lanes are numbered as in `enemy_spawned`.
+    #[func]
+    fn get_enemies_in_lane(&self, lane: i64) -> Array<Gd<Enemy>> {
+        let board = self.state.board();
+        let Some(lane) = usize::try_from(lane)
+            .ok()
+            .and_then(|lane| lanes(board).nth(lane))
+        else {
+            return Array::new();
+        };
+        self.enemies
+            .in_lane(&self.state, lane)
+            .into_iter()
+            .collect()
+    }
+
+    // How well the enemy pool is doing: enemies created, reused, recycled and freed,
+    // and how many are idle right now
+    #[func]
+    fn get_pool_stats(&self) -> Dictionary {
+        let stats = self.pool.stats;
+        dict! {
+            "created": stats.created,
+            "reused": stats.reused,
+            "recycled": stats.recycled,
+            "freed": stats.freed,
+            "idle": self.pool.idle.len() as i64,
+        }
+    }
+
+    // Plays a whole game with a bot strategy (1 greedy, 2 survival) without the scene,
+    // for checking difficulty curves. Returns the final points, goops, level and spawns.
+    #[func]
+    fn soak_test(strategy: i64, seed: i64, actions_per_spawn: i64, max_spawns: i64) -> Dictionary {
+        let Controller::Bot(mut strategy) = Controller::from_index(strategy) else {
+            godot_error!("Unknown bot strategy {}", strategy);
+            return Dictionary::new();
+        };
+
+        let result = soak(
+            strategy.as_mut(),
+            seed as u64,
+            actions_per_spawn.max(0) as u32,
+            max_spawns.max(0) as u32,
+        );
+        dict! {
+            "points": i64::try_from(result.points).unwrap_or(i64::MAX),
+            "goops": result.goops,
+            "level": result.level,
+            "spawns": result.spawns,
+            "lost": result.lost,
+        }
+    }
+
+    #[func]
+    fn spawn_enemy(&mut self) {
+        // While replaying, spawns are fed in by `due_replay_inputs`
+        if self.playback.is_none() {
+            self.spawn();
+        }
+    }
+
+    fn spawn(&mut self) {
+        self.record(ReplayInput::Spawn);
+
+        let spawn = self.state.random_spawn(&mut self.rng);
+        let events = self.state.spawn(spawn);
+        self.apply_events(&events);
+    }
+
+    // Runs a player command against the game state and updates the enemies to match.
+    // The events are returned so the caller can update its own view.
+    fn step(&mut self, command: Command) -> Vec<Event> {
+        self.record(ReplayInput::Command(command));
+
+        let events = self.state.step(command);
+        self.apply_events(&events);
+        events
+    }
+
+    fn record(&mut self, input: ReplayInput) {
+        if self.playback.is_none() {
+            self.replay.record(self.tick, input);
+        }
+    }
+
+    fn is_replaying(&self) -> bool {
+        self.playback.is_some()
+    }
+
+    // Inputs from the replay that should be played back on this frame
+    fn due_replay_inputs(&mut self) -> Vec<ReplayInput> {
+        let tick = self.tick;
+        self.playback
+            .as_mut()
+            .map(|playback| playback.due(tick))
+            .unwrap_or_default()
+    }
+
+    fn apply_events(&mut self, events: &[Event]) {
+        for event in events {
+            match *event {
+                Event::EnemySpawned {
+                    id,
+                    position,
+                    color,
+                    kind,
+                } => {
+                    let mut layer = self.enemy_layer();
+                    let mut enemy = self.pool.acquire(&mut layer);
+                    enemy.bind_mut().set_color(self.sprites.enemy(color, kind));
+                    enemy.set_position(self.state.board().to_vector(position));
+                    let scale = self.state.board().sprite_scale();
+                    enemy.bind_mut().appear(scale);
+                    self.enemies.insert(id, enemy);
+
+                    let board = self.state.board();
+                    let lane = Lane::at(board, position).map_or(0, |lane| lane.number(board));
+                    let args = [
+                        Variant::from(id.to_bits() as i64),
+                        Variant::from(lane as i64),
+                        Variant::from(lanes(board).count() as i64),
+                        Variant::from(color.0 as i64),
+                    ];
+                    self.base_mut().emit_signal("enemy_spawned".into(), &args);
+                }
+                Event::EnemyMoved { id, position } => {
+                    let target = self.state.board().to_vector(position);
+                    if let Some(mut enemy) = self.enemies.get(id) {
+                        enemy.bind_mut().move_to(target);
+                    }
+                }
+                Event::EnemyRecolored { id, color } => {
+                    let region = self.sprites.get(color).enemy;
+                    if let Some(mut enemy) = self.enemies.get(id) {
+                        enemy.bind_mut().set_color(region);
+                    }
+                }
+                Event::EnemyKilled {
+                    id,
+                    position,
+                    color,
+                    chain,
+                    points,
+                } => {
+                    if let Some(enemy) = self.enemies.remove(id) {
+                        self.show_popup(format!("+{}", points), position);
+                        self.kill_enemy_node(enemy);
+                    }
+
+                    let args = [
+                        Variant::from(id.to_bits() as i64),
+                        Variant::from(color.0 as i64),
+                        Variant::from(chain as i64 - 1),
+                    ];
+                    self.base_mut().emit_signal("enemy_killed".into(), &args);
+                }
+                Event::PowerUpTriggered { id, position, kind } => {
+                    let name = match kind {
+                        PieceKind::Normal => "",
+                        PieceKind::Bomb => "BOMB",
+                        PieceKind::Lightning => "ZAP",
+                        PieceKind::ColorWash => "WASH",
+                    };
+                    // Above the cell, so it doesn't cover the points of the kills it causes
+                    self.show_popup(
+                        name.to_string(),
+                        Position {
+                            x: position.x,
+                            y: position.y.saturating_sub(1),
+                        },
+                    );
+
+                    let args = [
+                        Variant::from(id.to_bits() as i64),
+                        Variant::from(kind as i64),
+                        Variant::from(position.x as i64),
+                        Variant::from(position.y as i64),
+                    ];
+                    self.base_mut()
+                        .emit_signal("power_up_triggered".into(), &args);
+                }
+                Event::EnemyRemoved { id } => {
+                    if let Some(enemy) = self.enemies.remove(id) {
+                        self.kill_enemy_node(enemy);
+                    }
+                }
+                Event::LevelCompleted { level } => {
+                    // Hold off spawning for a moment before the next level starts
+                    self.spawn_timer().stop();
+                    self.between_levels = true;
+                    let mut delay = self
+                        .base()
+                        .get_tree()
+                        .unwrap()
+                        // Keep the delay from running out while the game is paused
+                        .create_timer_ex(LEVEL_TRANSITION_TIME)
+                        .process_always(false)
+                        .done()
+                        .unwrap();
+                    delay.connect(
+                        "timeout".into(),
+                        Callable::from_object_method(&self.base(), "start_level"),
+                    );
+
+                    self.base_mut()
+                        .emit_signal("level_completed".into(), &[level.to_variant()]);
+                }
+                // If any enemy has reached the center, the run is over
+                Event::Lost => {
+                    self.spawn_timer().stop();
+                    if !self.is_replaying() {
+                        self.save_replay(LAST_REPLAY_PATH.into());
+                    }
+
+                    let args = [
+                        i64::try_from(self.state.points())
+                            .unwrap_or(i64::MAX)
+                            .to_variant(),
+                        (self.state.goops() as i64).to_variant(),
+                        self.elapsed.to_variant(),
+                    ];
+                    self.base_mut().emit_signal("game_over".into(), &args);
+                }
+                Event::ScoreChanged { points } => {
+                    let points = i64::try_from(points).unwrap_or(i64::MAX);
+                    self.base_mut()
+                        .emit_signal("score_changed".into(), &[points.to_variant()]);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn update_spawn_interval(&mut self) {
+        let wait_time = self.state.spawn_interval();
+        self.spawn_timer().set_wait_time(wait_time);
+        self.base_mut()
+            .emit_signal("speed_changed".into(), &[Variant::from(wait_time)]);
+    }
+
+    // Floats `text` up from the cell at `position`
+    fn show_popup(&mut self, text: String, position: Position) {
+        let mut popup = ScorePopup::new_alloc();
+        popup.set_text(text.into());
+        let board = self.state.board();
+        popup.set_position(board.to_vector(position) - Vector2::ONE * board.cell_size / 2.0);
+        self.enemy_layer().add_child(popup);
+    }
+
+    // Lets the enemy play its death before it goes back to the pool
+    fn kill_enemy_node(&mut self, mut enemy: Gd<Enemy>) {
+        let on_dead = Callable::from_object_method(&self.base(), "recycle_enemy")
+            .bindv(varray![enemy.clone()]);
+        enemy.bind_mut().die(on_dead);
+    }
+
+    // True while an enemy in `lane` is still on its way to its cell, so a shot along it
+    // would not match what is on screen
+    fn is_lane_moving(&self, lane: Lane) -> bool {
+        self.enemies
+            .in_lane(&self.state, lane)
+            .iter()
+            .any(|enemy| enemy.bind().is_moving())
+    }
+
+    // Reports where the grid, the enemy records and the enemy nodes disagree
+    fn check_consistency(&mut self) {
+        let mut problems: Vec<String> = self
+            .state
+            .inconsistencies()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        let board = self.state.board();
+        for (enemy_id, enemy) in self.state.enemies() {
+            match self.enemies.get(enemy_id) {
+                None => problems.push(format!("enemy {} has no node", enemy_id.to_bits())),
+                // Nodes only have to match once they stop moving
+                Some(node)
+                    if node.bind().motion == Motion::Idle
+                        && !node
+                            .get_position()
+                            .is_equal_approx(board.to_vector(enemy.position)) =>
+                {
+                    problems.push(format!(
+                        "node of enemy {} is not where the enemy is",
+                        enemy_id.to_bits()
+                    ));
+                }
+                Some(_) => (),
+            }
+        }
+        for (enemy_id, _) in self.enemies.iter() {
+            if self.state.enemy(enemy_id).is_none() {
+                problems.push(format!("node left over for enemy {}", enemy_id.to_bits()));
+            }
+        }
+
+        if problems != self.inconsistencies {
+            for problem in &problems {
+                godot_error!("Inconsistent field: {}", problem);
+            }
+            self.inconsistencies = problems;
+        }
+    }
+
+    fn is_between_levels(&self) -> bool {
+        self.between_levels
+    }
+
+    fn enemy_layer(&self) -> Gd<Node> {
+        self.enemy_layer
+            .clone()
+            .expect("enemy layer is checked in ready")
+    }
+
+    fn spawn_timer(&self) -> Gd<Timer> {
+        self.spawn_timer
+            .clone()
+            .expect("spawn timer is checked in ready")
+    }
+}
+
+#[derive(GodotClass)]
+#[class(init, base=Label)]
+struct Score {
+    base: Base<Label>,
+}
+
+#[godot_api]
+impl Score {
+    #[func]
+    fn on_score_changed(&mut self, points: i64) {
+        let text = points.to_string().into();
+        self.base_mut().set_text(text);
+    }
+}
+
+// "+N" shown where an enemy was killed. Floats up, fades out and frees itself.
+#[derive(GodotClass)]
+#[class(init, base=Label)]
+struct ScorePopup {
+    base: Base<Label>,
+}
+
+#[godot_api]
+impl ILabel for ScorePopup {
+    fn ready(&mut self) {
+        let font = load::<Font>("res://fonts/Covenant5x5.ttf");
+        self.base_mut().add_theme_font_override("font".into(), font);
+        self.base_mut()
+            .add_theme_font_size_override("font_size".into(), 8);
+        self.base_mut().set_z_index(1);
+
+        let target = self.base().get_position() + Vector2::new(0.0, -12.0);
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            self.base().clone(),
+            "position".into(),
+            Variant::from(target),
+            0.6,
+        );
+        tween.parallel();
+        tween.tween_property(
+            self.base().clone(),
+            "modulate:a".into(),
+            Variant::from(0.0),
+            0.6,
+        );
+        tween.tween_callback(Callable::from_object_method(&self.base(), "queue_free"));
+    }
+}
+
+#[derive(GodotClass)]
+#[class(init, base=Label)]
+struct LevelCounter {
+    base: Base<Label>,
+}
+
+#[godot_api]
+impl LevelCounter {
+    // Shows the level that comes next
+    #[func]
+    fn on_level_completed(&mut self, level: u32) {
+        let text = format!("LEVEL {}", level + 1).into();
+        self.base_mut().set_text(text);
+    }
+}
+
+// Panel shown at the end of a run. Hidden until the field emits `game_over`.
+#[derive(GodotClass)]
+#[class(init, base=PanelContainer)]
+struct Results {
+    // Field whose runs are shown and the table they are entered in. Without them
+    // the panel stays hidden.
+    #[export]
+    field: Option<Gd<Field>>,
+    #[export]
+    high_scores: Option<Gd<HighScores>>,
+    summary: String,
+    // Score and goops of a run that made the high scores and is waiting for a name
+    pending_entry: Option<(i64, i64)>,
+    // The restart key has to be released before it counts, so a key held down
+    // when the run ended or the name was submitted does not restart straight away
+    can_restart: bool,
+    base: Base<PanelContainer>,
+}
+
+#[godot_api]
+impl IPanelContainer for Results {
+    fn ready(&mut self) {
+        if self.field.is_none() || self.high_scores.is_none() {
+            godot_error!("Results has no field or high scores to show, disabling it");
+            self.base_mut().set_process_mode(ProcessMode::DISABLED);
+            return;
+        }
+
+        let mut name_entry = self.base().get_node_as::<LineEdit>("Box/NameEntry");
+        name_entry.set_max_length(MAX_NAME_LENGTH as i32);
+        name_entry.hide();
+    }
+
+    fn process(&mut self, _dt: f64) {
+        if !self.base().is_visible() || self.pending_entry.is_some() {
+            return;
+        }
+
+        let input = Input::singleton();
+        if !input.is_action_pressed("restart".into()) {
+            self.can_restart = true;
+        } else if self.can_restart && input.is_action_just_pressed("restart".into()) {
+            self.base().get_tree().unwrap().reload_current_scene();
+        }
+    }
+}
+
+#[godot_api]
+impl Results {
+    #[func]
+    fn show_results(&mut self, score: i64, goops: i64, duration: f64) {
+        let Some(high_scores) = self.high_scores.clone() else {
+            return;
+        };
+
+        let seconds = duration as i64;
+        self.summary = format!(
+            "GAME OVER\n\nSCORE {}  GOOPS {}  TIME {}:{:02}\n\n",
+            score,
+            goops,
+            seconds / 60,
+            seconds % 60
+        );
+
+        if high_scores.bind().qualifies(score) {
+            self.pending_entry = Some((score, goops));
+            self.set_text("NEW HIGH SCORE\nENTER YOUR NAME".to_string());
+
+            let mut name_entry = self.base().get_node_as::<LineEdit>("Box/NameEntry");
+            name_entry.show();
+            name_entry.grab_focus();
+        } else {
+            self.show_table(&high_scores);
+        }
+
+        self.base_mut().show();
+    }
+
+    #[func]
+    fn submit_name(&mut self, name: GString) {
+        let (Some(field), Some(mut high_scores)) = (self.field.clone(), self.high_scores.clone())
+        else {
+            return;
+        };
+        let Some((score, goops)) = self.pending_entry.take() else {
+            return;
+        };
+
+        let (level, seed) = {
+            let field = field.bind();
+            (field.state.level().number() as i64, field.get_seed())
+        };
+
+        high_scores
+            .bind_mut()
+            .insert(name, score, goops, level, seed);
+
+        self.base().get_node_as::<LineEdit>("Box/NameEntry").hide();
+        self.can_restart = false;
+        self.show_table(&high_scores);
+    }
+
+    fn show_table(&mut self, high_scores: &Gd<HighScores>) {
+        let mut text = "HIGH SCORES\n".to_string();
+        for (rank, entry) in high_scores.bind().table.entries().iter().enumerate() {
+            text += &format!("{}. {}  {}\n", rank + 1, entry.name, entry.score);
+        }
+        text += "\nPRESS ENTER";
+        self.set_text(text);
+    }
+
+    fn set_text(&mut self, text: String) {
+        let mut label = self.base().get_node_as::<Label>("Box/Label");
+        label.set_text(format!("{}{}", self.summary, text).into());
+    }
+}
+
+// Overlay toggled by the pause action. Pausing the tree stops the spawn timer and
+// every tween bound to a game node, so they pick up exactly where they left off.
+// This node has to be set to always process so it can unpause.
+#[derive(GodotClass)]
+#[class(init, base=PanelContainer)]
+struct PauseMenu {
+    // Field that is paused. Without it the menu disables itself.
+    #[export]
+    field: Option<Gd<Field>>,
+    base: Base<PanelContainer>,
+}
+
+#[godot_api]
+impl IPanelContainer for PauseMenu {
+    fn ready(&mut self) {
+        if self.field.is_none() {
+            godot_error!("PauseMenu has no field to pause, disabling it");
+            self.base_mut().set_process_mode(ProcessMode::DISABLED);
+        }
+    }
+
+    fn process(&mut self, _dt: f64) {
+        if !Input::singleton().is_action_just_pressed("pause".into()) {
+            return;
+        }
+
+        if self.base().is_visible() {
+            self.resume();
+        } else {
+            // There is nothing left to pause once the run is over
+            if self
+                .field
+                .as_ref()
+                .is_some_and(|field| !field.bind().state.is_lost())
+            {
+                self.pause();
+            }
+        }
+    }
+}
+
+#[godot_api]
+impl PauseMenu {
+    fn pause(&mut self) {
+        self.base().get_tree().unwrap().set_pause(true);
+        self.base_mut().show();
+        self.base().get_node_as::<Button>("Box/Resume").grab_focus();
+    }
+
+    #[func]
+    fn resume(&mut self) {
+        self.base_mut().hide();
+        self.base().get_tree().unwrap().set_pause(false);
+    }
+
+    #[func]
+    fn restart(&mut self) {
+        let mut tree = self.base().get_tree().unwrap();
+        tree.set_pause(false);
+        tree.reload_current_scene();
+    }
+
+    #[func]
+    fn quit_to_menu(&mut self) {
+        let mut tree = self.base().get_tree().unwrap();
+        tree.set_pause(false);
+        tree.change_scene_to_file(MENU_SCENE.into());
+    }
+}
+
+// Title screen
+#[derive(GodotClass)]
+#[class(init, base=Control)]
+struct Menu {
+    base: Base<Control>,
 }
 
-// Allows colors to be randomly generated
-impl Distribution<Color> for Standard {
-    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Color {
-        match rng.gen_range(0..4) {
-            0 => Color::Red,
-            1 => Color::Green,
-            2 => Color::Blue,
-            3 => Color::Purple,
-            _ => unreachable!(),
+#[godot_api]
+impl IControl for Menu {
+    fn ready(&mut self) {
+        load_input_profile();
+    }
+
+    fn process(&mut self, _dt: f64) {
+        let input = Input::singleton();
+        let scene = if input.is_action_just_pressed("ui_accept".into()) {
+            GAME_SCENE
+        } else if input.is_action_just_pressed("controls".into()) {
+            CONTROLS_SCENE
+        } else {
+            return;
+        };
+        self.base()
+            .get_tree()
+            .unwrap()
+            .change_scene_to_file(scene.into());
+    }
+}
+
+// Contents of a text file in `user://`, or `None` if it hasn't been saved yet
+fn read_user_file(path: &str) -> Option<String> {
+    if !FileAccess::file_exists(path.into()) {
+        return None;
+    }
+    Some(FileAccess::get_file_as_string(path.into()).to_string())
+}
+
+// Replaces a text file in `user://`
+fn write_user_file(path: &str, text: String) {
+    let Some(mut file) = FileAccess::open(path.into(), ModeFlags::WRITE) else {
+        godot_error!("Could not write {}", path);
+        return;
+    };
+    file.store_string(text.into());
+}
+
+// Applies the bindings saved in `user://` over the ones from the project settings
+fn load_input_profile() {
+    let Some(text) = read_user_file(INPUT_PROFILE_PATH) else {
+        return;
+    };
+    match InputProfile::decode(&text) {
+        Ok(profile) => apply_input_profile(&profile),
+        Err(err) => godot_warn!("Ignoring input profile {}: {}", INPUT_PROFILE_PATH, err),
+    }
+}
+
+// Bindings of the rebindable and reserved actions as the input map has them now
+fn current_input_profile() -> InputProfile {
+    let mut input_map = InputMap::singleton();
+    let mut profile = InputProfile::default();
+    for action in REBINDABLE_ACTIONS.into_iter().chain(RESERVED_ACTIONS) {
+        profile.add_action(action);
+        for event in input_map.action_get_events(action.into()).iter_shared() {
+            // Events the profile has no place for, like stick motion, are left out
+            if let Some(binding) = binding_of(event) {
+                let _ = profile.bind(action, binding);
+            }
+        }
+    }
+    profile
+}
+
+// Replaces the events of every action in the profile. Events the profile can't
+// describe are kept, so the directions stay on the stick.
+fn apply_input_profile(profile: &InputProfile) {
+    let mut input_map = InputMap::singleton();
+    for (action, bindings) in profile.actions() {
+        if !input_map.has_action(action.into()) {
+            godot_warn!("Input profile binds unknown action {}", action);
+            continue;
+        }
+        for event in input_map.action_get_events(action.into()).iter_shared() {
+            if binding_of(event.clone()).is_some() {
+                input_map.action_erase_event(action.into(), event);
+            }
+        }
+        for &binding in bindings {
+            input_map.action_add_event(action.into(), event_of(binding));
+        }
+    }
+}
+
+fn save_input_profile(profile: &InputProfile) {
+    write_user_file(INPUT_PROFILE_PATH, profile.encode());
+}
+
+fn binding_of(event: Gd<InputEvent>) -> Option<Binding> {
+    match event.try_cast::<InputEventKey>() {
+        // Keys without a physical keycode come from virtual keyboards and can't be saved
+        Ok(key) => Some(key.get_physical_keycode())
+            .filter(|&code| code != Key::NONE)
+            .map(|code| Binding::Key(code.ord())),
+        Err(event) => event
+            .try_cast::<InputEventJoypadButton>()
+            .ok()
+            .map(|button| Binding::Button(button.get_button_index().ord())),
+    }
+}
+
+fn event_of(binding: Binding) -> Gd<InputEvent> {
+    match binding {
+        Binding::Key(code) => {
+            let mut key = InputEventKey::new_gd();
+            key.set_physical_keycode(Key::from_ord(code));
+            key.upcast()
+        }
+        Binding::Button(code) => {
+            let mut button = InputEventJoypadButton::new_gd();
+            button.set_button_index(JoyButton::from_ord(code));
+            button.upcast()
+        }
+    }
+}
+
+// How a binding is shown on the controls screen
+fn binding_name(binding: Binding) -> String {
+    match binding {
+        Binding::Key(code) => OS::singleton()
+            .get_keycode_string(Key::from_ord(code))
+            .to_string()
+            .to_uppercase(),
+        Binding::Button(code) => format!("PAD {code}"),
+    }
+}
+
+// Screen for rebinding actions. Each action gets a row with its bindings and buttons
+// to add or clear them; adding waits for the next key or gamepad button.
+#[derive(GodotClass)]
+#[class(init, base=Control)]
+struct ControlsMenu {
+    profile: InputProfile,
+    // Action waiting for a new binding
+    listening: Option<String>,
+    base: Base<Control>,
+}
+
+#[godot_api]
+impl IControl for ControlsMenu {
+    fn ready(&mut self) {
+        self.profile = current_input_profile();
+
+        let mut rows = self.base().get_node_as::<VBoxContainer>("Box/Rows");
+        for action in REBINDABLE_ACTIONS {
+            let mut row = HBoxContainer::new_alloc();
+            row.set_name(action.into());
+
+            let mut name = Label::new_alloc();
+            name.set_text(action.to_uppercase().into());
+            name.set_custom_minimum_size(Vector2::new(48.0, 0.0));
+            row.add_child(name.upcast());
+
+            let mut bindings = Label::new_alloc();
+            bindings.set_name("Bindings".into());
+            bindings.set_h_size_flags(SizeFlags::EXPAND_FILL);
+            row.add_child(bindings.upcast());
+
+            for (text, method) in [("ADD", "listen"), ("CLEAR", "clear_action")] {
+                let mut button = Button::new_alloc();
+                button.set_text(text.into());
+                let callable = Callable::from_object_method(&self.base(), method)
+                    .bindv(varray![GString::from(action)]);
+                button.connect("pressed".into(), callable);
+                row.add_child(button.upcast());
+            }
+            rows.add_child(row.upcast());
+        }
+
+        self.refresh(String::new());
+        self.base().get_node_as::<Button>("Box/Back").grab_focus();
+    }
+
+    fn input(&mut self, event: Gd<InputEvent>) {
+        let Some(action) = self.listening.clone() else {
+            return;
+        };
+        if !event.is_pressed() || event.is_echo() {
+            return;
+        }
+        // Only keys and buttons can be bound, anything else is left to the buttons
+        let Some(binding) = binding_of(event) else {
+            return;
+        };
+        self.base().get_viewport().unwrap().set_input_as_handled();
+        self.listening = None;
+
+        if binding == Binding::Key(Key::ESCAPE.ord()) {
+            self.refresh(String::new());
+            return;
+        }
+        let status = match self.profile.bind(&action, binding) {
+            Ok(()) => {
+                apply_input_profile(&self.profile);
+                save_input_profile(&self.profile);
+                String::new()
+            }
+            Err(BindingError::Conflict(owner)) => format!(
+                "{} IS ALREADY BOUND TO {}",
+                binding_name(binding),
+                owner.to_uppercase()
+            ),
+            Err(err) => err.to_string().to_uppercase(),
+        };
+        self.refresh(status);
+    }
+}
+
+#[godot_api]
+impl ControlsMenu {
+    #[func]
+    fn listen(&mut self, action: GString) {
+        let action = action.to_string();
+        self.refresh(format!(
+            "PRESS A KEY OR BUTTON FOR {}, ESC CANCELS",
+            action.to_uppercase()
+        ));
+        self.listening = Some(action);
+    }
+
+    #[func]
+    fn clear_action(&mut self, action: GString) {
+        self.listening = None;
+        if let Err(err) = self.profile.clear(&action.to_string()) {
+            godot_error!("Could not clear bindings: {}", err);
+            return;
+        }
+        apply_input_profile(&self.profile);
+        save_input_profile(&self.profile);
+        self.refresh(String::new());
+    }
+
+    // Goes back to the bindings from the project settings and forgets the saved ones
+    #[func]
+    fn reset(&mut self) {
+        self.listening = None;
+        InputMap::singleton().load_from_project_settings();
+        self.profile = current_input_profile();
+        if FileAccess::file_exists(INPUT_PROFILE_PATH.into()) {
+            DirAccess::remove_absolute(INPUT_PROFILE_PATH.into());
+        }
+        self.refresh("CONTROLS RESET".to_string());
+    }
+
+    #[func]
+    fn back(&mut self) {
+        self.base()
+            .get_tree()
+            .unwrap()
+            .change_scene_to_file(MENU_SCENE.into());
+    }
+
+    // Shows every action's bindings and `status` below them. Actions left without
+    // bindings are pointed out, since they can't be used until they get one.
+    fn refresh(&mut self, status: String) {
+        for action in REBINDABLE_ACTIONS {
+            let names: Vec<_> = self
+                .profile
+                .bindings(action)
+                .iter()
+                .map(|&binding| binding_name(binding))
+                .collect();
+            let mut label = self
+                .base()
+                .get_node_as::<Label>(format!("Box/Rows/{action}/Bindings").as_str());
+            label.set_text(names.join(", ").into());
         }
-    }
-}
 
-#[derive(Debug, Clone, Copy, Default)]
-enum Tile {
-    #[default]
-    None,
-    Player,
-    Enemy(EnemyId),
+        let unbound = REBINDABLE_ACTIONS
+            .into_iter()
+            .find(|action| self.profile.bindings(action).is_empty());
+        let status = match unbound {
+            Some(action) if status.is_empty() => {
+                format!("{} HAS NO BINDINGS", action.to_uppercase())
+            }
+            _ => status,
+        };
+        let mut label = self.base().get_node_as::<Label>("Box/Status");
+        label.set_text(status.into());
+    }
 }
 
+// Plays the sound effects and keeps the bus volumes. It only reacts to signals of the
+// field and player, which the scene connects to it, so gameplay code knows nothing
+// of sound.
 #[derive(GodotClass)]
-#[class(init, base=TileMap)]
-struct Field {
-    rng: ThreadRng,
-    grid: [[Tile; GRID_HEIGHT]; GRID_WIDTH],
-    next_enemy_id: EnemyId,
-    // Used to associate enemy IDs with Godot instances
-    enemies: HashMap<EnemyId, i64>,
-    last_direction: Option<Direction>,
-    goops: u16,
-    base: Base<TileMap>,
+#[class(init, base=Node)]
+struct AudioManager {
+    // Sliders for the effect and music volumes, if any
+    #[export]
+    sfx_slider: Option<Gd<Range>>,
+    #[export]
+    music_slider: Option<Gd<Range>>,
+    // Sounds left empty are skipped
+    #[export]
+    move_sound: Option<Gd<AudioStream>>,
+    #[export]
+    shoot_sound: Option<Gd<AudioStream>>,
+    #[export]
+    kill_sound: Option<Gd<AudioStream>>,
+    #[export]
+    swap_sound: Option<Gd<AudioStream>>,
+    #[export]
+    spawn_sound: Option<Gd<AudioStream>>,
+    #[export]
+    speed_up_sound: Option<Gd<AudioStream>>,
+    #[export]
+    game_over_sound: Option<Gd<AudioStream>>,
+    // How many effects can play at once. The oldest one is cut off for a new one.
+    #[export]
+    #[init(default = 8)]
+    voices: i64,
+    players: Vec<Gd<AudioStreamPlayer>>,
+    next_player: usize,
+    // Kill sounds with the time they are due, so a chain plays out note by note
+    queued_kills: VecDeque<(f64, f32)>,
+    // Seconds the manager has been processed for
+    clock: f64,
+    // Last time between spawns, to tell a speed-up from the reset at a new level
+    wait_time: Option<f64>,
+    settings: AudioSettings,
+    base: Base<Node>,
 }
 
 #[godot_api]
-impl ITileMap for Field {
+impl INode for AudioManager {
     fn ready(&mut self) {
-        self.rng = thread_rng();
-
-        for x in 0..GRID_WIDTH {
-            for y in 0..GRID_HEIGHT {
-                // True if x is within 4x4 player field
-                let x_in_center = x >= MIN_CENTER_X && x <= MAX_CENTER_X;
-                // True if y is within 4x4 player field
-                let y_in_center = y >= MIN_CENTER_Y && y <= MAX_CENTER_Y;
+        self.settings = load_audio_settings();
+        apply_audio_settings(&self.settings);
+
+        for _ in 0..self.voices.max(1) {
+            let mut player = AudioStreamPlayer::new_alloc();
+            player.set_bus(SFX_BUS.into());
+            self.base_mut().add_child(player.clone().upcast());
+            self.players.push(player);
+        }
 
-                let (i, j) = match (x_in_center, y_in_center) {
-                    // If both are in center, use tile is in center. Use sprite located at (0, 0).
-                    (true, true) => (0, 0),
-                    // If only one is in center, use tile is on edge. Use sprite located at (1, 0).
-                    (true, false) | (false, true) => (1, 0),
-                    // If neither are in center, use tile is in corner. Use sprite located at (2, 0).
-                    (false, false) => (2, 0),
-                };
+        // Set the sliders before connecting them, so this doesn't save the settings again
+        let sliders = [
+            (self.sfx_slider.clone(), self.settings.sfx, "set_sfx_volume"),
+            (
+                self.music_slider.clone(),
+                self.settings.music,
+                "set_music_volume",
+            ),
+        ];
+        for (slider, volume, method) in sliders {
+            let Some(mut slider) = slider else {
+                continue;
+            };
+            slider.set_min(0.0);
+            slider.set_max(1.0);
+            slider.set_step(0.05);
+            slider.set_value(volume as f64);
+            slider.connect(
+                "value_changed".into(),
+                Callable::from_object_method(&self.base(), method),
+            );
+        }
+    }
 
-                self.base_mut()
-                    // first argument is layer
-                    // second argument is position of cell in tilemap
-                    .set_cell_ex(0, Vector2i::new(x as i32, y as i32))
-                    // the ID of the tileset
-                    .source_id(0)
-                    // coordinates of the sprite in the tileset
-                    .atlas_coords(Vector2i::new(i, j))
-                    .done();
+    fn process(&mut self, dt: f64) {
+        self.clock += dt;
+        while let Some(&(due, pitch)) = self.queued_kills.front() {
+            if due > self.clock {
+                break;
             }
+            self.queued_kills.pop_front();
+            self.play(self.kill_sound.clone(), pitch);
         }
     }
 }
 
 #[godot_api]
-impl Field {
+impl AudioManager {
     #[func]
-    fn spawn_enemy(&mut self) {
-        let mut positions = Vec::new();
-
-        // Enemies cannot spawn in the same quadrant twice in a row
-        if self.last_direction != Some(Direction::Down) {
-            // Creates a list of positions at the top of the field
-            // Enemy is facing the down direction
-            positions.extend(
-                (MIN_CENTER_X..=MAX_CENTER_X).map(|x| (Direction::Down, Position { x, y: 0 })),
-            )
-        }
+    fn on_player_moved(&mut self, _x: i64, _y: i64) {
+        self.play(self.move_sound.clone(), 1.0);
+    }
 
-        if self.last_direction != Some(Direction::Up) {
-            positions.extend((MIN_CENTER_X..=MAX_CENTER_X).map(|x| {
-                (
-                    Direction::Up,
-                    Position {
-                        x,
-                        y: GRID_HEIGHT - 1,
-                    },
-                )
-            }))
-        }
+    #[func]
+    fn on_shot_fired(&mut self, _direction: i64, _goops: i64) {
+        self.play(self.shoot_sound.clone(), 1.0);
+    }
 
-        if self.last_direction != Some(Direction::Right) {
-            positions.extend(
-                (MIN_CENTER_Y..=MAX_CENTER_Y).map(|y| (Direction::Right, Position { x: 0, y })),
-            )
-        }
+    #[func]
+    fn on_color_swapped(&mut self, _old: i64, _new: i64) {
+        self.play(self.swap_sound.clone(), 1.0);
+    }
 
-        if self.last_direction != Some(Direction::Left) {
-            positions.extend((MIN_CENTER_Y..=MAX_CENTER_Y).map(|y| {
-                (
-                    Direction::Left,
-                    Position {
-                        x: GRID_WIDTH - 1,
-                        y,
-                    },
-                )
-            }))
-        }
+    // Each kill of a chain comes a little later and higher than the one before
+    #[func]
+    fn on_enemy_killed(&mut self, _id: i64, _color: i64, chain_index: i64) {
+        let chain_index = chain_index.max(0);
+        let due = self.clock + chain_index as f64 * CHAIN_NOTE_GAP;
+        let pitch = chain_pitch(chain_index as u32);
+        self.queued_kills.push_back((due, pitch));
+    }
 
-        // Choose a random position at the end of one of the quadrants
-        let (direction, position) = positions.choose(&mut self.rng).unwrap();
-        self.last_direction = Some(*direction);
+    #[func]
+    fn on_enemy_spawned(&mut self, _id: i64, lane: i64, lane_count: i64, _color: i64) {
+        let pitch = lane_pitch(lane.max(0) as u32, lane_count.max(0) as u32);
+        self.play(self.spawn_sound.clone(), pitch);
+    }
 
-        // Move all enemies closer to the center
-        match direction {
-            Direction::Right => {
-                for i in (0..GRID_WIDTH / 2).rev() {
-                    match self.grid[i][position.y] {
-                        Tile::Enemy(enemy_id) => {
-                            let mut enemy: Gd<Enemy> =
-                                instance_from_id(self.enemies[&enemy_id]).unwrap().cast();
-                            enemy.bind_mut().move_to(Position {
-                                x: i + 1,
-                                y: position.y,
-                            });
-                            // Current position will be overwritten in a later loop
-                            self.grid[i + 1][position.y] = self.grid[i][position.y];
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            Direction::Left => {
-                for i in GRID_WIDTH / 2..GRID_WIDTH {
-                    match self.grid[i][position.y] {
-                        Tile::Enemy(enemy_id) => {
-                            let mut enemy: Gd<Enemy> =
-                                instance_from_id(self.enemies[&enemy_id]).unwrap().cast();
-                            enemy.bind_mut().move_to(Position {
-                                x: i - 1,
-                                y: position.y,
-                            });
-                            self.grid[i - 1][position.y] = self.grid[i][position.y];
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            Direction::Down => {
-                for i in (0..GRID_HEIGHT / 2).rev() {
-                    match self.grid[position.x][i] {
-                        Tile::Enemy(enemy_id) => {
-                            let mut enemy: Gd<Enemy> =
-                                instance_from_id(self.enemies[&enemy_id]).unwrap().cast();
-                            enemy.bind_mut().move_to(Position {
-                                x: position.x,
-                                y: i + 1,
-                            });
-                            self.grid[position.x][i + 1] = self.grid[position.x][i];
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            Direction::Up => {
-                for i in GRID_HEIGHT / 2..GRID_HEIGHT {
-                    match self.grid[position.x][i] {
-                        Tile::Enemy(enemy_id) => {
-                            let mut enemy: Gd<Enemy> =
-                                instance_from_id(self.enemies[&enemy_id]).unwrap().cast();
-                            enemy.bind_mut().move_to(Position {
-                                x: position.x,
-                                y: i - 1,
-                            });
-                            self.grid[position.x][i - 1] = self.grid[position.x][i];
-                        }
-                        _ => (),
-                    }
-                }
-            }
+    #[func]
+    fn on_speed_changed(&mut self, wait_time: f64) {
+        if self.wait_time.is_some_and(|last| wait_time < last) {
+            self.play(self.speed_up_sound.clone(), 1.0);
         }
+        self.wait_time = Some(wait_time);
+    }
 
-        // Instantiate a new enemy from the enemy scene
-        let scene = load::<PackedScene>("res://enemy.tscn");
-        let mut enemy: Gd<Enemy> = scene.instantiate().unwrap().cast();
-        let instance_id = enemy.instance_id().to_i64();
-        enemy.bind_mut().set_color(self.rng.gen());
-        enemy.set_position(position.to_vector());
+    #[func]
+    fn on_game_over(&mut self, _score: i64, _goops: i64, _duration: f64) {
+        self.queued_kills.clear();
+        self.play(self.game_over_sound.clone(), 1.0);
+    }
 
-        let mut root = self.base().get_node_as::<Node2D>("..");
-        root.add_child(enemy.clone());
+    #[func]
+    fn set_sfx_volume(&mut self, volume: f64) {
+        self.settings.sfx = volume.clamp(0.0, 1.0) as f32;
+        apply_audio_settings(&self.settings);
+        save_audio_settings(&self.settings);
+    }
 
-        // Add the enemy to the field data
-        self.grid[position.x][position.y] = Tile::Enemy(self.next_enemy_id);
-        self.enemies.insert(self.next_enemy_id, instance_id);
-        self.next_enemy_id += 1;
+    #[func]
+    fn set_music_volume(&mut self, volume: f64) {
+        self.settings.music = volume.clamp(0.0, 1.0) as f32;
+        apply_audio_settings(&self.settings);
+        save_audio_settings(&self.settings);
+    }
 
-        // If any enemy as reached the center, restart the level
-        if self.check_lose_condition() {
-            self.base().get_tree().unwrap().reload_current_scene();
+    fn play(&mut self, sound: Option<Gd<AudioStream>>, pitch: f32) {
+        let Some(sound) = sound else {
+            return;
+        };
+        // Signals can arrive before ready has made the players
+        if self.players.is_empty() {
+            return;
         }
+        let mut player = self.players[self.next_player].clone();
+        self.next_player = (self.next_player + 1) % self.players.len();
+        player.set_stream(sound);
+        player.set_pitch_scale(pitch);
+        player.play();
     }
+}
 
-    fn get_enemy(&self, enemy_id: EnemyId) -> Gd<Enemy> {
-        instance_from_id(self.enemies[&enemy_id]).unwrap().cast()
+// Plays the music as stems of equal length that loop together. The first stem always
+// plays, the others fade in as the intensity rises, and the whole track speeds up.
+#[derive(GodotClass)]
+#[class(init, base=Node)]
+struct MusicController {
+    // Field the pace and threat are read from. Without it the music stays calm.
+    #[export]
+    field: Option<Gd<Field>>,
+    #[export]
+    stems: Array<Gd<AudioStream>>,
+    // Share of the level's pace in the intensity, the rest is how close enemies are
+    #[export]
+    #[init(default = 0.5)]
+    pace_weight: f64,
+    // Seconds the intensity takes to follow the game
+    #[export]
+    #[init(default = 0.5)]
+    response: f64,
+    // How much faster the music plays at full intensity, e.g. 0.1 for 10%
+    #[export]
+    #[init(default = 0.1)]
+    max_speed_up: f32,
+    // From 0 to 1, for graphing while tuning
+    #[var(get = get_intensity)]
+    intensity: f64,
+    tracker: Intensity,
+    players: Vec<Gd<AudioStreamPlayer>>,
+    base: Base<Node>,
+}
+
+#[godot_api]
+impl INode for MusicController {
+    fn ready(&mut self) {
+        self.tracker = Intensity::new(self.pace_weight, self.response);
+
+        for stem in self.stems.iter_shared() {
+            let mut player = AudioStreamPlayer::new_alloc();
+            player.set_bus(MUSIC_BUS.into());
+            player.set_stream(stem);
+            self.base_mut().add_child(player.clone().upcast());
+            self.players.push(player);
+        }
+        // The stems are the same length, so the first one finishing restarts them all
+        let loop_music = Callable::from_object_method(&self.base(), "loop_music");
+        if let Some(first) = self.players.first_mut() {
+            first.connect("finished".into(), loop_music);
+        }
+        self.update_players();
+        self.loop_music();
     }
 
-    // Check if an enemy has reached the center
-    fn check_lose_condition(&self) -> bool {
-        for x in MIN_CENTER_X..=MAX_CENTER_X {
-            for y in MIN_CENTER_Y..=MAX_CENTER_Y {
-                match self.grid[x][y] {
-                    Tile::Enemy(_) => return true,
-                    _ => (),
+    fn process(&mut self, dt: f64) {
+        let (pace, threat) = match &self.field {
+            Some(field) => {
+                let field = field.bind();
+                if field.state.is_lost() {
+                    return;
                 }
+                (
+                    level::pace(field.state.spawn_interval()),
+                    field.state.threat(),
+                )
             }
+            None => (0.0, 0.0),
+        };
+        self.intensity = self.tracker.next(self.intensity, pace, threat, dt);
+        self.update_players();
+    }
+}
+
+#[godot_api]
+impl MusicController {
+    #[func]
+    fn get_intensity(&self) -> f64 {
+        self.intensity
+    }
+
+    #[func]
+    fn loop_music(&mut self) {
+        for player in &mut self.players {
+            player.play();
         }
-        false
     }
 
-    // This function finds the closest enemy from `position` in `direction`
-    fn find_enemy(&self, position: Position, direction: Direction) -> Option<(EnemyId, Position)> {
-        match direction {
-            Direction::Left => {
-                for x in (0..position.x).rev() {
-                    match self.grid[x][position.y] {
-                        // Break out of loop at the first enemy found
-                        Tile::Enemy(enemy_id) => {
-                            return Some((enemy_id, Position { x, y: position.y }))
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            Direction::Right => {
-                for x in position.x + 1..GRID_WIDTH {
-                    match self.grid[x][position.y] {
-                        Tile::Enemy(enemy_id) => {
-                            return Some((enemy_id, Position { x, y: position.y }))
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            Direction::Up => {
-                for y in (0..position.y).rev() {
-                    match self.grid[position.x][y] {
-                        Tile::Enemy(enemy_id) => {
-                            return Some((enemy_id, Position { x: position.x, y }))
-                        }
-                        _ => (),
-                    }
-                }
-            }
-            Direction::Down => {
-                for y in position.y + 1..GRID_HEIGHT {
-                    match self.grid[position.x][y] {
-                        Tile::Enemy(enemy_id) => {
-                            return Some((enemy_id, Position { x: position.x, y }))
-                        }
-                        _ => (),
-                    }
-                }
-            }
+    #[func]
+    fn on_game_over(&mut self, _score: i64, _goops: i64, _duration: f64) {
+        for player in &mut self.players {
+            player.stop();
         }
-        None
     }
 
-    fn remove_enemy(&mut self, enemy_id: EnemyId, position: Position) {
-        let mut enemy = self.get_enemy(enemy_id);
-        enemy.queue_free();
-        self.enemies.remove(&enemy_id);
-        self.grid[position.x][position.y] = Tile::None;
+    fn update_players(&mut self) {
+        let count = self.players.len();
+        let speed = 1.0 + self.intensity as f32 * self.max_speed_up.max(0.0);
+        for (index, player) in self.players.iter_mut().enumerate() {
+            player.set_volume_db(volume_to_db(stem_volume(index, count, self.intensity)));
+            player.set_pitch_scale(speed);
+        }
     }
+}
 
-    fn add_goops(&mut self, goops: u16) {
-        self.goops += goops;
-        // Enemies spawn 10% faster for every 20 enemies killed
-        let wait_time = 0.9_f64.powf((self.goops / 20) as f64);
+fn load_audio_settings() -> AudioSettings {
+    let Some(text) = read_user_file(AUDIO_SETTINGS_PATH) else {
+        return AudioSettings::default();
+    };
+    AudioSettings::decode(&text).unwrap_or_else(|err| {
+        godot_warn!("Ignoring audio settings {}: {}", AUDIO_SETTINGS_PATH, err);
+        AudioSettings::default()
+    })
+}
 
-        let mut timer = self.base().get_node_as::<Timer>("Timer");
-        timer.set_wait_time(wait_time);
+fn apply_audio_settings(settings: &AudioSettings) {
+    let mut server = AudioServer::singleton();
+    for (bus, volume) in [(SFX_BUS, settings.sfx), (MUSIC_BUS, settings.music)] {
+        let index = server.get_bus_index(bus.into());
+        if index < 0 {
+            godot_warn!("There is no {} audio bus", bus);
+            continue;
+        }
+        server.set_bus_volume_db(index, volume_to_db(volume));
+        server.set_bus_mute(index, volume <= 0.0);
     }
 }
 
+fn save_audio_settings(settings: &AudioSettings) {
+    write_user_file(AUDIO_SETTINGS_PATH, settings.encode());
+}
+
+// Top scores kept across runs in `user://`
 #[derive(GodotClass)]
-#[class(init, base=Label)]
-struct Score {
-    points: u16,
-    base: Base<Label>,
+#[class(init, base=Node)]
+struct HighScores {
+    table: HighScoreTable,
+    base: Base<Node>,
 }
 
-impl Score {
-    fn add_points(&mut self, goops: u16) {
-        // Killing multiple enemies in one move gives bonus points
-        for i in 1..=goops {
-            self.points += 100 * i;
+#[godot_api]
+impl INode for HighScores {
+    fn ready(&mut self) {
+        let Some(text) = read_user_file(HIGH_SCORES_PATH) else {
+            return;
+        };
+        match HighScoreTable::decode(&text) {
+            Ok(table) => self.table = table,
+            // Start over with an empty table, the file is replaced on the next insert
+            Err(err) => godot_warn!("Ignoring high scores in {}: {}", HIGH_SCORES_PATH, err),
         }
+    }
+}
 
-        let text = self.points.to_string().into();
-        self.base_mut().set_text(text);
+#[godot_api]
+impl HighScores {
+    // True if a run with `score` would make it onto the table
+    #[func]
+    fn qualifies(&self, score: i64) -> bool {
+        self.table.qualifies(score.max(0) as u64)
+    }
+
+    // Adds a run dated today and saves the table. Returns its rank starting at 0, or -1.
+    #[func]
+    fn insert(&mut self, name: GString, score: i64, goops: i64, level: i64, seed: i64) -> i64 {
+        let entry = HighScoreEntry {
+            name: name.to_string(),
+            score: score.max(0) as u64,
+            goops: goops.max(0) as u32,
+            level: level.max(0) as u32,
+            date: Time::singleton().get_date_string_from_system().to_string(),
+            seed,
+        };
+
+        let Some(rank) = self.table.insert(entry) else {
+            return -1;
+        };
+        self.save();
+        rank as i64
+    }
+
+    // Entries from highest to lowest score, as dictionaries
+    #[func]
+    fn get_entries(&self) -> Array<Dictionary> {
+        let mut entries = Array::new();
+        for entry in self.table.entries() {
+            entries.push(dict! {
+                "name": entry.name.clone(),
+                "score": entry.score as i64,
+                "goops": entry.goops,
+                "level": entry.level,
+                "date": entry.date.clone(),
+                "seed": entry.seed,
+            });
+        }
+        entries
+    }
+
+    fn save(&self) {
+        write_user_file(HIGH_SCORES_PATH, self.table.encode());
     }
 }
 
 #[derive(GodotClass)]
 #[class(init, base=Sprite2D)]
 struct Player {
+    // Who plays: the keyboard or one of the bot strategies
+    #[export(enum = (Keyboard, Greedy, Survival))]
+    control: i64,
+    // Field the player plays on. Without it the player disables itself.
+    #[export]
+    field: Option<Gd<Field>>,
+    // How many commands given during a move or shot are kept, and for how many seconds
+    #[export]
+    #[init(default = 3)]
+    buffer_size: i64,
+    #[export]
+    #[init(default = 0.25)]
+    buffer_window: f64,
+    // How far a gamepad stick has to be pushed to press a direction, how far it can
+    // fall back before releasing it, and how many degrees past the diagonal it has
+    // to turn to change it
+    #[export]
+    #[init(default = 0.5)]
+    stick_deadzone: f32,
+    #[export]
+    #[init(default = 0.35)]
+    stick_release: f32,
+    #[export]
+    #[init(default = 15.0)]
+    stick_hysteresis: f32,
+    // Seconds a direction has to be held before it repeats, and between repeats
+    #[export]
+    #[init(default = 0.17)]
+    repeat_delay: f64,
+    #[export]
+    #[init(default = 0.05)]
+    repeat_interval: f64,
+    // How many pixels a finger has to travel for a swipe instead of a tap
+    #[export]
+    #[init(default = 12.0)]
+    swipe_distance: f32,
+    controller: Controller,
+    // Commands waiting for the player to be free, stamped with `clock`
+    input_buffer: InputBuffer,
+    // Seconds the player has been processed for
+    clock: f64,
+    // Where the sprite rests and faces and its color, mirrored from the game state
     position: Position,
     direction: Direction,
     color: Color,
-    is_moving: bool,
-    is_shooting: bool,
+    board: BoardConfig,
+    sprites: SpriteTable,
+    motion: PlayerMotion,
+    // Set once the consistency check has reported the player out of place
+    out_of_place: bool,
     base: Base<Sprite2D>,
 }
 
 #[godot_api]
 impl ISprite2D for Player {
     fn ready(&mut self) {
-        let mut field = self.base().get_node_as::<Field>("../Field");
-        let mut field = field.bind_mut();
-
-        self.set_color(field.rng.gen());
-        self.set_direction(Direction::Up);
+        self.controller = Controller::from_index(self.control);
+        if let Controller::Human(input) = &mut self.controller {
+            input.stick = StickQuantizer::new(
+                self.stick_deadzone,
+                self.stick_release,
+                self.stick_hysteresis,
+            );
+            input.repeat = AutoRepeat::new(self.repeat_delay, self.repeat_interval);
+            input.touch.tracker = TouchTracker::new(self.swipe_distance);
+        }
+        self.input_buffer = InputBuffer::new(self.buffer_size.max(1) as usize, self.buffer_window);
 
-        // Set the player's position at a random position in the center
-        let x = field.rng.gen_range(MIN_CENTER_X..=MAX_CENTER_X);
-        let y = field.rng.gen_range(MIN_CENTER_Y..=MAX_CENTER_Y);
-        self.set_position(Position { x, y }, &mut field);
+        let Some(field) = self.field.clone() else {
+            godot_error!("Player has no field to play on, disabling it");
+            self.base_mut().set_process_mode(ProcessMode::DISABLED);
+            return;
+        };
+        let player = *field.bind().state.player();
+        self.board = *field.bind().state.board();
+        self.sprites = field.bind().sprites.clone();
+
+        self.set_color(player.color);
+        self.set_direction(player.direction);
+        self.position = player.position;
+        let (position, scale) = (
+            self.board.to_vector(player.position),
+            self.board.sprite_scale(),
+        );
+        self.base_mut().set_position(position);
+        self.base_mut().set_scale(scale);
     }
 
-    fn process(&mut self, _dt: f64) {
-        if !self.is_shooting {
-            let input = Input::singleton();
-
-            // Move in the direction of button press
-            if !self.is_moving {
-                if input.is_action_just_pressed("left".into()) {
-                    self.set_direction(Direction::Left);
-                    self.move_to(-1, 0);
-                } else if input.is_action_just_pressed("right".into()) {
-                    self.set_direction(Direction::Right);
-                    self.move_to(1, 0);
-                } else if input.is_action_just_pressed("up".into()) {
-                    self.set_direction(Direction::Up);
-                    self.move_to(0, -1);
-                } else if input.is_action_just_pressed("down".into()) {
-                    self.set_direction(Direction::Down);
-                    self.move_to(0, 1);
+    fn process(&mut self, dt: f64) {
+        self.clock += dt;
+        let mut field = self.field();
+        // No more input once the run is over
+        if field.bind().state.is_lost() {
+            self.transition(PlayerTrigger::Die);
+            return;
+        }
+        if self.motion == PlayerMotion::Stunned && !field.bind().is_between_levels() {
+            self.transition(PlayerTrigger::Recover);
+        }
+        if cfg!(debug_assertions) {
+            self.check_consistency(&field.bind().state);
+        }
+
+        if field.bind().is_replaying() {
+            // Recorded inputs already passed the checks below when they were made
+            let inputs = field.bind_mut().due_replay_inputs();
+            for input in inputs {
+                match input {
+                    ReplayInput::Spawn => field.bind_mut().spawn(),
+                    ReplayInput::Command(command) => self.execute(command),
                 }
             }
+            return;
+        }
 
-            if input.is_action_just_pressed("shoot".into()) {
-                let mut field = self.base().get_node_as::<Field>("../Field");
-                let mut field = field.bind_mut();
-
-                let mut position = self.position;
-                let mut goops = 0;
+        // Bots only decide once their last command has run
+        let can_decide = self.motion.accepts_commands() && self.input_buffer.is_empty();
+        for command in self.controller.poll(&field.bind().state, can_decide, dt) {
+            self.input_buffer.push(self.clock, command);
+        }
 
-                // Kill every enemy of the same color until one can no longer be found
-                while let Some((enemy_id, enemy_position)) =
-                    field.find_enemy(position, self.direction)
-                {
-                    let mut enemy = field.get_enemy(enemy_id);
-                    let mut enemy = enemy.bind_mut();
-
-                    // Updating the position reduces the required computation for `find_enemy`
-                    position = enemy_position;
-
-                    if self.color == enemy.color {
-                        field.remove_enemy(enemy_id, enemy_position);
-                        goops += 1;
-                    } else {
-                        // If the color does not match, swap the player and enemy color, then break out of loop
-                        let color = self.color;
-                        self.set_color(enemy.color);
-                        enemy.set_color(color);
-                        break;
-                    }
+        // A move or shot has to finish before the next command
+        while self.motion.accepts_commands() {
+            let Some(command) = self.input_buffer.peek(self.clock) else {
+                break;
+            };
+            // A shot waits in the buffer for the enemies in its lane to stop moving
+            if command == Command::Shoot {
+                let lane = Lane::facing(field.bind().state.player());
+                if field.bind().is_lane_moving(lane) {
+                    break;
                 }
+            }
+            self.input_buffer.pop(self.clock);
+            self.execute(command);
+        }
+    }
 
-                // This increases the difficulty for each kill
-                field.add_goops(goops);
+    fn unhandled_input(&mut self, event: Gd<InputEvent>) {
+        let Ok(touch) = event.try_cast::<InputEventScreenTouch>() else {
+            return;
+        };
+        // Touches come in screen coords, the board is laid out in the parent's
+        let position = match self
+            .base()
+            .get_parent()
+            .and_then(|parent| parent.try_cast::<CanvasItem>().ok())
+        {
+            Some(parent) => {
+                parent.get_global_transform_with_canvas().affine_inverse() * touch.get_position()
+            }
+            None => touch.get_position(),
+        };
+        let Controller::Human(input) = &mut self.controller else {
+            return;
+        };
 
-                // Increase score based on number of killed enemies
-                if goops > 0 {
-                    let mut score = self.base().get_node_as::<Score>("../Score");
-                    let mut score = score.bind_mut();
-                    score.add_points(goops);
+        let finger = touch.get_index();
+        if touch.is_pressed() {
+            input.touch.tracker.press(finger, position.x, position.y);
+            return;
+        }
+        match input.touch.tracker.release(finger, position.x, position.y) {
+            Some(Gesture::Tap { x, y }) => {
+                if let Some(cell) = self.board.cell_at(Vector2::new(x, y)) {
+                    input.touch.tap(&self.board, cell);
                 }
-
-                self.shoot(position);
-                self.is_shooting = true;
             }
+            Some(Gesture::Swipe(direction)) => input.touch.swipe(direction),
+            None => {}
+        }
+    }
+}
+
+// Where the player's commands come from
+enum Controller {
+    // Keyboard and gamepads
+    Human(HumanInput),
+    Bot(Box<dyn Strategy>),
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::Human(HumanInput::default())
+    }
+}
+
+impl Controller {
+    fn from_index(index: i64) -> Self {
+        match index {
+            1 => Self::Bot(Box::new(Greedy)),
+            2 => Self::Bot(Box::new(Survival)),
+            _ => Self::default(),
+        }
+    }
+
+    // Commands for this frame. Buttons are read every frame so presses during a move
+    // are buffered, bots are only asked when `can_decide` is set.
+    fn poll(&mut self, state: &GameState, can_decide: bool, dt: f64) -> Vec<Command> {
+        match self {
+            Self::Human(input) => input.poll(state, can_decide, dt),
+            // Bots wait for every move to finish, so they play at the pace a human would
+            Self::Bot(strategy) if can_decide => strategy.decide(state).into_iter().collect(),
+            Self::Bot(_) => Vec::new(),
+        }
+    }
+}
+
+const DIRECTION_ACTIONS: [(Direction, &str); 4] = [
+    (Direction::Left, "left"),
+    (Direction::Right, "right"),
+    (Direction::Up, "up"),
+    (Direction::Down, "down"),
+];
+
+// Direction buttons and keys go through the input map, the left stick of any
+// gamepad is read directly so it can be quantized with hysteresis. Touches are
+// fed in by the player as they come.
+#[derive(Default)]
+struct HumanInput {
+    stick: StickQuantizer,
+    repeat: AutoRepeat,
+    touch: TouchInput,
+}
+
+impl HumanInput {
+    fn poll(&mut self, state: &GameState, ready: bool, dt: f64) -> Vec<Command> {
+        let mut input = Input::singleton();
+        let mut commands = Vec::new();
+
+        let (x, y) = Self::stick(&mut input);
+        let stick = self.stick.update(x, y);
+        // A newly pressed direction wins over one that is still held
+        let pressed = |action: &str| input.is_action_pressed(action.into());
+        let just_pressed = DIRECTION_ACTIONS
+            .iter()
+            .find(|(_, action)| input.is_action_just_pressed((*action).into()));
+        let still_held = DIRECTION_ACTIONS
+            .iter()
+            .find(|(direction, action)| self.repeat.held() == Some(*direction) && pressed(action));
+        let any_held = DIRECTION_ACTIONS.iter().find(|(_, action)| pressed(action));
+        let held = just_pressed
+            .or(still_held)
+            .or(any_held)
+            .map(|(direction, _)| *direction)
+            .or(stick);
+        commands.extend(self.repeat.update(held, dt, ready).map(Command::Move));
+        // Buttons take over from whatever a touch started
+        if held.is_some() {
+            self.touch.cancel();
         }
+
+        if input.is_action_just_pressed("shoot".into()) {
+            commands.push(Command::Shoot);
+        }
+        if ready && commands.is_empty() {
+            commands.extend(self.touch.next(state.player()));
+        }
+
+        commands
+    }
+
+    // Left stick of whichever gamepad is pushed furthest
+    fn stick(input: &mut Gd<Input>) -> (f32, f32) {
+        input
+            .get_connected_joypads()
+            .iter_shared()
+            .map(|device| {
+                (
+                    input.get_joy_axis(device as i32, JoyAxis::LEFT_X),
+                    input.get_joy_axis(device as i32, JoyAxis::LEFT_Y),
+                )
+            })
+            .max_by(|a, b| a.0.hypot(a.1).total_cmp(&b.0.hypot(b.1)))
+            .unwrap_or((0.0, 0.0))
     }
 }
 
 #[godot_api]
 impl Player {
+    // Colors are indices into the field's palette
+    #[signal]
+    fn color_swapped(old: i64, new: i64);
+
+    // Cell the player moved to
+    #[signal]
+    fn player_moved(x: i64, y: i64);
+
+    // Directions are 0 left, 1 right, 2 up and 3 down.
+    // `goops` is how many enemies the shot killed.
+    #[signal]
+    fn shot_fired(direction: i64, goops: i64);
+
+    // States are 0 idle, 1 moving, 2 shooting out, 3 returning, 4 stunned and 5 dead
+    #[signal]
+    fn state_changed(old: i64, new: i64);
+
     #[func]
     fn end_movement(&mut self) {
-        self.is_moving = false;
+        self.transition(PlayerTrigger::MoveFinished);
     }
 
     // Return to original position after shooting
     #[func]
     fn return_to_position(&mut self) {
+        self.transition(PlayerTrigger::ShotLanded);
         let mut tween = self.base_mut().create_tween().unwrap();
         tween.tween_property(
             self.base().clone(),
             "position".into(),
-            Variant::from(self.position.to_vector()),
+            Variant::from(self.board.to_vector(self.position)),
             0.15,
         );
         tween.tween_callback(Callable::from_object_method(&self.base(), "end_shoot"));
@@ -491,16 +2176,79 @@ impl Player {
     #[func]
     fn end_shoot(&mut self) {
         self.set_direction(self.direction.opposite());
-        self.is_shooting = false;
+        self.transition(PlayerTrigger::Returned);
+        // The shot that cleared a level leaves the player stunned until the next one starts
+        if self.field().bind().is_between_levels() {
+            self.transition(PlayerTrigger::Stun);
+        }
     }
 
-    fn set_position(&mut self, position: Position, field: &mut Field) {
-        // Update player's position in grid
-        field.grid[self.position.x][self.position.y] = Tile::None;
-        field.grid[position.x][position.y] = Tile::Player;
+    // Reports when the player has settled somewhere other than where the game state has it
+    fn check_consistency(&mut self, state: &GameState) {
+        let position = state.player().position;
+        let out_of_place = self.position != position
+            || (self.motion == PlayerMotion::Idle
+                && !self
+                    .base()
+                    .get_position()
+                    .is_equal_approx(self.board.to_vector(position)));
+        if out_of_place && !self.out_of_place {
+            godot_error!("Player node is not where the player is");
+        }
+        self.out_of_place = out_of_place;
+    }
 
-        self.position = position;
-        self.base_mut().set_position(position.to_vector());
+    // Moves to the state `trigger` leads to. Triggers that are not allowed in the current
+    // state are ignored, which only happens if an animation finishes late.
+    fn transition(&mut self, trigger: PlayerTrigger) {
+        let Some(next) = self.motion.next(trigger) else {
+            return;
+        };
+        let old = std::mem::replace(&mut self.motion, next);
+        let args = [Variant::from(old as i64), Variant::from(next as i64)];
+        self.base_mut().emit_signal("state_changed".into(), &args);
+    }
+
+    fn field(&self) -> Gd<Field> {
+        self.field.clone().expect("field is checked in ready")
+    }
+
+    // Sends a command to the field and plays back whatever happened to the player
+    fn execute(&mut self, command: Command) {
+        let mut field = self.field();
+        let events = field.bind_mut().step(command);
+
+        for event in events {
+            match event {
+                Event::PlayerTurned { direction } => self.set_direction(direction),
+                Event::PlayerMoved { position } => {
+                    self.move_to(position);
+                    let args = [
+                        Variant::from(position.x as i64),
+                        Variant::from(position.y as i64),
+                    ];
+                    self.base_mut().emit_signal("player_moved".into(), &args);
+                }
+                Event::PlayerRecolored { color } => {
+                    let args = [
+                        Variant::from(self.color.0 as i64),
+                        Variant::from(color.0 as i64),
+                    ];
+                    self.set_color(color);
+                    self.base_mut().emit_signal("color_swapped".into(), &args);
+                }
+                Event::ShotFired {
+                    direction,
+                    target,
+                    goops,
+                } => {
+                    self.shoot(target);
+                    let args = [Variant::from(direction as i64), Variant::from(goops as i64)];
+                    self.base_mut().emit_signal("shot_fired".into(), &args);
+                }
+                _ => (),
+            }
+        }
     }
 
     fn set_direction(&mut self, direction: Direction) {
@@ -517,50 +2265,24 @@ impl Player {
 
     fn set_color(&mut self, color: Color) {
         self.color = color;
-
-        // Change the sprite's region based on new color
-        let position = match color {
-            Color::Red => Vector2::new(0.0, 16.0),
-            Color::Green => Vector2::new(16.0, 16.0),
-            Color::Blue => Vector2::new(32.0, 16.0),
-            Color::Purple => Vector2::new(48.0, 16.0),
-        };
-        self.base_mut()
-            .set_region_rect(Rect2::new(position, Vector2::new(16.0, 16.0)));
+        let region = self.sprites.get(color).player;
+        self.base_mut().set_region_rect(region);
     }
 
-    fn move_to(&mut self, dx: isize, dy: isize) {
-        let mut next_x = (self.position.x as isize + dx) as usize;
-        let mut next_y = (self.position.y as isize + dy) as usize;
-
-        // Prevent player from going out of x bounds
-        if next_x < MIN_CENTER_X {
-            next_x = MIN_CENTER_X;
-        } else if next_x > MAX_CENTER_X {
-            next_x = MAX_CENTER_X;
-        }
-
-        // Prevent player from going out of y bounds
-        if next_y < MIN_CENTER_Y {
-            next_y = MIN_CENTER_Y;
-        } else if next_y > MAX_CENTER_Y {
-            next_y = MAX_CENTER_Y;
-        }
-
-        self.position.x = next_x;
-        self.position.y = next_y;
+    fn move_to(&mut self, position: Position) {
+        self.position = position;
 
         // Tween to the next screen position
         let mut tween = self.base_mut().create_tween().unwrap();
         tween.tween_property(
             self.base().clone(),
             "position".into(),
-            Variant::from(self.position.to_vector()),
+            Variant::from(self.board.to_vector(self.position)),
             0.1,
         );
         tween.tween_callback(Callable::from_object_method(&self.base(), "end_movement"));
 
-        self.is_moving = true;
+        self.transition(PlayerTrigger::Move);
     }
 
     // Move to the position that was shot at
@@ -569,47 +2291,126 @@ impl Player {
         tween.tween_property(
             self.base().clone(),
             "position".into(),
-            Variant::from(position.to_vector()),
+            Variant::from(self.board.to_vector(position)),
             0.15,
         );
         tween.tween_callback(Callable::from_object_method(
             &self.base(),
             "return_to_position",
         ));
+        self.transition(PlayerTrigger::Shoot);
     }
 }
 
 #[derive(GodotClass)]
 #[class(init, base=Sprite2D)]
 struct Enemy {
-    color: Color,
+    motion: Motion,
+    tween: Option<Gd<Tween>>,
+    // Moves waiting for the current one to finish
+    queued_moves: VecDeque<Vector2>,
     base: Base<Sprite2D>,
 }
 
+// What an enemy's sprite is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Motion {
+    #[default]
+    Idle,
+    // Appearing or moving to a cell
+    Moving,
+    // Shrinking away after being killed or cleared
+    Dying,
+}
+
+#[godot_api]
 impl Enemy {
-    fn set_color(&mut self, color: Color) {
-        self.color = color;
+    // Emitted when the enemy has caught up with every move it was given
+    #[signal]
+    fn movement_finished();
 
-        // Change the sprite's region based on new color
-        let position = match color {
-            Color::Red => Vector2::new(0.0, 32.0),
-            Color::Green => Vector2::new(16.0, 32.0),
-            Color::Blue => Vector2::new(32.0, 32.0),
-            Color::Purple => Vector2::new(48.0, 32.0),
-        };
-        self.base_mut()
-            .set_region_rect(Rect2::new(position, Vector2::new(16.0, 16.0)));
+    #[func]
+    fn end_movement(&mut self) {
+        self.tween = None;
+        if let Some(target) = self.queued_moves.pop_front() {
+            self.start_move(target);
+        } else {
+            self.motion = Motion::Idle;
+            self.base_mut().emit_signal("movement_finished".into(), &[]);
+        }
     }
+}
 
-    fn move_to(&mut self, position: Position) {
+impl Enemy {
+    fn is_moving(&self) -> bool {
+        self.motion == Motion::Moving
+    }
+
+    // Stops any animation in progress, so the enemy can go back to the pool
+    fn stop(&mut self) {
+        if let Some(mut tween) = self.tween.take() {
+            tween.kill();
+        }
+        self.queued_moves.clear();
+        self.motion = Motion::Idle;
+    }
+
+    // Grows from nothing to `scale` where the enemy was placed
+    fn appear(&mut self, scale: Vector2) {
+        self.stop();
+        self.base_mut().set_scale(Vector2::ZERO);
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            self.base().clone(),
+            "scale".into(),
+            Variant::from(scale),
+            0.1,
+        );
+        tween.tween_callback(Callable::from_object_method(&self.base(), "end_movement"));
+        self.tween = Some(tween);
+        self.motion = Motion::Moving;
+    }
+
+    // Shrinks away, then calls `on_dead`
+    fn die(&mut self, on_dead: Callable) {
+        self.stop();
+        let mut tween = self.base_mut().create_tween().unwrap();
+        tween.tween_property(
+            self.base().clone(),
+            "scale".into(),
+            Variant::from(Vector2::ZERO),
+            0.1,
+        );
+        tween.tween_callback(on_dead);
+        self.tween = Some(tween);
+        self.motion = Motion::Dying;
+    }
+
+    // Takes the region of the spritesheet the new color is drawn at
+    fn set_color(&mut self, region: Rect2) {
+        self.base_mut().set_region_rect(region);
+    }
+
+    // Moves given while the enemy is still moving play after the current one
+    fn move_to(&mut self, target: Vector2) {
+        if self.is_moving() {
+            self.queued_moves.push_back(target);
+        } else {
+            self.start_move(target);
+        }
+    }
+
+    fn start_move(&mut self, target: Vector2) {
         // Tween to the next screen position
         let mut tween = self.base_mut().create_tween().unwrap();
         tween.tween_property(
             self.base().clone(),
             "position".into(),
-            Variant::from(position.to_vector()),
+            Variant::from(target),
             0.1,
         );
         tween.tween_callback(Callable::from_object_method(&self.base(), "end_movement"));
+        self.tween = Some(tween);
+        self.motion = Motion::Moving;
     }
 }
@@ -0,0 +1,51 @@
+// How intense the music is. The pace of the level and the threat on the board combine
+// into one value from 0 to 1, which fades in more stems and speeds the music up.
+
+// Intensity over which a stem fades in once it is reached
+const STEM_FADE: f64 = 0.15;
+
+// How the intensity follows the game. The value itself is kept by the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Intensity {
+    // Share of the pace in the intensity, the rest is threat
+    pace_weight: f64,
+    // Seconds the intensity takes to get most of the way to a new level, so single
+    // spawns and kills don't make the music jump
+    response: f64,
+}
+
+impl Default for Intensity {
+    fn default() -> Self {
+        Self::new(0.5, 0.5)
+    }
+}
+
+impl Intensity {
+    pub fn new(pace_weight: f64, response: f64) -> Self {
+        Self {
+            pace_weight: pace_weight.clamp(0.0, 1.0),
+            response,
+        }
+    }
+
+    // Moves `current` towards the intensity for `pace` and `threat`, both from 0 to 1
+    pub fn next(&self, current: f64, pace: f64, threat: f64, dt: f64) -> f64 {
+        let target = (pace * self.pace_weight + threat * (1.0 - self.pace_weight)).clamp(0.0, 1.0);
+        let blend = if self.response > 0.0 {
+            1.0 - (-dt / self.response).exp()
+        } else {
+            1.0
+        };
+        current + (target - current) * blend
+    }
+}
+
+// Linear volume of stem `index` out of `count`. The first stem always plays, the
+// others come in one after another as the intensity rises.
+pub fn stem_volume(index: usize, count: usize, intensity: f64) -> f32 {
+    if index == 0 {
+        return 1.0;
+    }
+    let start = index as f64 / count as f64;
+    ((intensity - start) / STEM_FADE).clamp(0.0, 1.0) as f32
+}
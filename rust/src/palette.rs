@@ -0,0 +1,88 @@
+// Colors pieces can have. The palette decides how many there are and how often
+// each one spawns; what they look like is up to the view.
+
+use rand::prelude::*;
+use std::fmt;
+
+// Index of a color in the palette
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Color(pub u8);
+
+pub const MAX_COLORS: usize = u8::MAX as usize + 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaletteError {
+    Empty,
+    TooManyColors(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "palette has no colors"),
+            Self::TooManyColors(count) => {
+                write!(
+                    f,
+                    "palette has {count} colors, at most {MAX_COLORS} are allowed"
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    // Relative spawn weight of each color
+    weights: Vec<u32>,
+}
+
+// Red, green, blue, purple, orange and yellow, all equally likely
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            weights: vec![1; 6],
+        }
+    }
+}
+
+impl Palette {
+    pub fn new(weights: Vec<u32>) -> Result<Self, PaletteError> {
+        if weights.is_empty() {
+            return Err(PaletteError::Empty);
+        }
+        if weights.len() > MAX_COLORS {
+            return Err(PaletteError::TooManyColors(weights.len()));
+        }
+        Ok(Self { weights })
+    }
+
+    pub fn weights(&self) -> &[u32] {
+        &self.weights
+    }
+
+    pub fn color_count(&self) -> usize {
+        self.weights.len()
+    }
+
+    // Picks one of the first `count` colors by weight. If none of them has any
+    // weight, they are all equally likely.
+    pub fn choose<R: Rng + ?Sized>(&self, count: usize, rng: &mut R) -> Color {
+        let weights = &self.weights[..count.clamp(1, self.color_count())];
+        let total = weights
+            .iter()
+            .fold(0_u32, |total, &weight| total.saturating_add(weight));
+        if total == 0 {
+            return Color(rng.gen_range(0..weights.len()) as u8);
+        }
+
+        let mut roll = rng.gen_range(0..total);
+        for (index, &weight) in weights.iter().enumerate() {
+            if roll < weight {
+                return Color(index as u8);
+            }
+            roll -= weight;
+        }
+        // Only reachable when the total saturated
+        Color((weights.len() - 1) as u8)
+    }
+}
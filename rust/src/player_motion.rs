@@ -0,0 +1,119 @@
+// What the player's sprite is doing. Moves and shots play out as animations, and
+// the player only takes a new command once the last one has finished, so commands
+// always start from the position the game state has.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PlayerMotion {
+    #[default]
+    Idle,
+    Moving,
+    // Flying out to the last enemy hit
+    ShootingOut,
+    // Flying back after a shot
+    Returning,
+    // Can't act, e.g. while the board is cleared between levels
+    Stunned,
+    Dead,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerTrigger {
+    Move,
+    MoveFinished,
+    Shoot,
+    ShotLanded,
+    Returned,
+    Stun,
+    Recover,
+    Die,
+}
+
+impl PlayerMotion {
+    // The state `trigger` leads to, or `None` if it is not allowed in this one
+    pub fn next(self, trigger: PlayerTrigger) -> Option<Self> {
+        use PlayerMotion::*;
+
+        match (self, trigger) {
+            (Dead, _) => None,
+            (_, PlayerTrigger::Die) => Some(Dead),
+            (Idle, PlayerTrigger::Move) => Some(Moving),
+            (Moving, PlayerTrigger::MoveFinished) => Some(Idle),
+            (Idle, PlayerTrigger::Shoot) => Some(ShootingOut),
+            (ShootingOut, PlayerTrigger::ShotLanded) => Some(Returning),
+            (Returning, PlayerTrigger::Returned) => Some(Idle),
+            (Idle | Moving, PlayerTrigger::Stun) => Some(Stunned),
+            (Stunned, PlayerTrigger::Recover) => Some(Idle),
+            _ => None,
+        }
+    }
+
+    pub fn accepts_commands(self) -> bool {
+        self == Self::Idle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PlayerMotion::*;
+    use super::*;
+
+    const STATES: [PlayerMotion; 6] = [Idle, Moving, ShootingOut, Returning, Stunned, Dead];
+    const TRIGGERS: [PlayerTrigger; 8] = [
+        PlayerTrigger::Move,
+        PlayerTrigger::MoveFinished,
+        PlayerTrigger::Shoot,
+        PlayerTrigger::ShotLanded,
+        PlayerTrigger::Returned,
+        PlayerTrigger::Stun,
+        PlayerTrigger::Recover,
+        PlayerTrigger::Die,
+    ];
+
+    // Every legal transition. Any other pair has to be rejected.
+    const TRANSITIONS: [(PlayerMotion, PlayerTrigger, PlayerMotion); 13] = [
+        (Idle, PlayerTrigger::Move, Moving),
+        (Idle, PlayerTrigger::Shoot, ShootingOut),
+        (Idle, PlayerTrigger::Stun, Stunned),
+        (Idle, PlayerTrigger::Die, Dead),
+        (Moving, PlayerTrigger::MoveFinished, Idle),
+        (Moving, PlayerTrigger::Stun, Stunned),
+        (Moving, PlayerTrigger::Die, Dead),
+        (ShootingOut, PlayerTrigger::ShotLanded, Returning),
+        (ShootingOut, PlayerTrigger::Die, Dead),
+        (Returning, PlayerTrigger::Returned, Idle),
+        (Returning, PlayerTrigger::Die, Dead),
+        (Stunned, PlayerTrigger::Recover, Idle),
+        (Stunned, PlayerTrigger::Die, Dead),
+    ];
+
+    #[test]
+    fn transitions_match_table() {
+        for state in STATES {
+            for trigger in TRIGGERS {
+                let expected = TRANSITIONS
+                    .iter()
+                    .find(|&&(from, on, _)| from == state && on == trigger)
+                    .map(|&(_, _, to)| to);
+                assert_eq!(state.next(trigger), expected, "{state:?} on {trigger:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_commands_while_busy() {
+        assert_eq!(Moving.next(PlayerTrigger::Shoot), None);
+        assert_eq!(Moving.next(PlayerTrigger::Move), None);
+        assert_eq!(ShootingOut.next(PlayerTrigger::Move), None);
+        assert_eq!(Stunned.next(PlayerTrigger::Shoot), None);
+        for state in STATES {
+            assert_eq!(state.accepts_commands(), state == Idle);
+        }
+    }
+
+    #[test]
+    fn dead_is_terminal() {
+        for trigger in TRIGGERS {
+            assert_eq!(Dead.next(trigger), None, "Dead on {trigger:?}");
+        }
+    }
+}
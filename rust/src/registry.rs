@@ -0,0 +1,130 @@
+// Storage for enemies addressed by generational ids. Removing an enemy frees its
+// slot for the next one, and ids of removed enemies stop resolving instead of
+// finding whatever took their slot.
+
+use std::ops::Index;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct EnemyId {
+    index: u32,
+    generation: u32,
+}
+
+impl EnemyId {
+    // Packs the id into one number, for handing it to scripts
+    pub fn to_bits(self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Registry<T> {
+    slots: Vec<Slot<T>>,
+    // Indices of empty slots
+    free: Vec<u32>,
+}
+
+impl<T> Default for Registry<T> {
+    fn default() -> Self {
+        Self {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Registry<T> {
+    pub fn insert(&mut self, value: T) -> EnemyId {
+        match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(value);
+                EnemyId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                EnemyId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, id: EnemyId) -> Option<&T> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    pub fn get_mut(&mut self, id: EnemyId) -> Option<&mut T> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    pub fn remove(&mut self, id: EnemyId) -> Option<T> {
+        let slot = self
+            .slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)?;
+        let value = slot.value.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(id.index);
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EnemyId, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let id = EnemyId {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            slot.value.as_ref().map(|value| (id, value))
+        })
+    }
+}
+
+// For ids that are known to be live, such as the ones on the grid
+impl<T> Index<EnemyId> for Registry<T> {
+    type Output = T;
+
+    fn index(&self, id: EnemyId) -> &T {
+        self.get(id).expect("enemy id is stale")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_ids_stop_resolving_once_their_slot_is_reused() {
+        let mut registry = Registry::default();
+        let first = registry.insert("first");
+        assert_eq!(registry.remove(first), Some("first"));
+
+        let second = registry.insert("second");
+        assert_eq!(second.index, first.index);
+        assert_ne!(second, first);
+        assert_eq!(registry.get(first), None);
+        assert_eq!(registry.get_mut(first), None);
+        assert_eq!(registry.remove(first), None);
+        assert_eq!(registry.get(second), Some(&"second"));
+        assert_eq!(registry.iter().collect::<Vec<_>>(), [(second, &"second")]);
+    }
+}
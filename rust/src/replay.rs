@@ -0,0 +1,334 @@
+// Recording of every input that affects a run, so it can be played back exactly.
+//
+// File layout (all integers little endian):
+//   magic "GOOP", format version (u8), seed (i64),
+//   base points (u64), chain bonus index (u8),
+//   board width, height and center size (u16 each),
+//   palette color count (u16) followed by the weight of each color (u32 each),
+//   then one entry per input: tick delta since the previous entry (LEB128), input code (u8)
+//
+// Only the current version is read. The rules and the random draws have changed
+// since older replays were recorded, so they would no longer play back the same run.
+
+use crate::board::BoardConfig;
+use crate::game::{Command, Direction};
+use crate::palette::{Palette, PaletteError};
+use crate::scoring::{ChainBonus, ScoringModel};
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"GOOP";
+const VERSION: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayInput {
+    Command(Command),
+    // The spawn timer fired
+    Spawn,
+}
+
+impl ReplayInput {
+    fn code(&self) -> u8 {
+        match self {
+            Self::Command(Command::Move(Direction::Left)) => 0,
+            Self::Command(Command::Move(Direction::Right)) => 1,
+            Self::Command(Command::Move(Direction::Up)) => 2,
+            Self::Command(Command::Move(Direction::Down)) => 3,
+            Self::Command(Command::Shoot) => 4,
+            Self::Spawn => 5,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0 => Self::Command(Command::Move(Direction::Left)),
+            1 => Self::Command(Command::Move(Direction::Right)),
+            2 => Self::Command(Command::Move(Direction::Up)),
+            3 => Self::Command(Command::Move(Direction::Down)),
+            4 => Self::Command(Command::Shoot),
+            5 => Self::Spawn,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    UnknownInput(u8),
+    UnknownChainBonus(u8),
+    InvalidPalette(PaletteError),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a replay file"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported replay version {version}")
+            }
+            Self::Truncated => write!(f, "replay file is truncated"),
+            Self::UnknownInput(code) => write!(f, "unknown input code {code}"),
+            Self::UnknownChainBonus(index) => write!(f, "unknown chain bonus {index}"),
+            Self::InvalidPalette(err) => write!(f, "invalid palette: {err}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Replay {
+    pub seed: i64,
+    // Points the run was scored with
+    pub scoring: ScoringModel,
+    // Only the dimensions are stored, the cell size is left at its default
+    pub board: BoardConfig,
+    // Colors were drawn with these weights
+    pub palette: Palette,
+    // Inputs with the tick they happened on, in the order they happened
+    pub inputs: Vec<(u64, ReplayInput)>,
+}
+
+impl Replay {
+    pub fn new(seed: i64, board: BoardConfig, scoring: ScoringModel, palette: Palette) -> Self {
+        Self {
+            seed,
+            board,
+            scoring,
+            palette,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, tick: u64, input: ReplayInput) {
+        self.inputs.push((tick, input));
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let weights = self.palette.weights();
+        let mut bytes = Vec::with_capacity(30 + weights.len() * 4 + self.inputs.len() * 2);
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.seed.to_le_bytes());
+        bytes.extend_from_slice(&self.scoring.base_points.to_le_bytes());
+        bytes.push(self.scoring.chain_bonus.index() as u8);
+        for size in [self.board.width, self.board.height, self.board.center_size] {
+            bytes.extend_from_slice(&(size as u16).to_le_bytes());
+        }
+        bytes.extend_from_slice(&(weights.len() as u16).to_le_bytes());
+        for weight in weights {
+            bytes.extend_from_slice(&weight.to_le_bytes());
+        }
+
+        let mut last_tick = 0;
+        for (tick, input) in &self.inputs {
+            write_varint(&mut bytes, tick - last_tick);
+            bytes.push(input.code());
+            last_tick = *tick;
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, ReplayError> {
+        if bytes.len() < 5 || &bytes[0..4] != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = bytes[4];
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+        let mut rest = &bytes[5..];
+        let seed = i64::from_le_bytes(take(&mut rest)?);
+
+        let base_points = u64::from_le_bytes(take(&mut rest)?);
+        let [chain_bonus] = take(&mut rest)?;
+        let scoring = ScoringModel {
+            base_points,
+            chain_bonus: ChainBonus::from_index(chain_bonus as i64)
+                .ok_or(ReplayError::UnknownChainBonus(chain_bonus))?,
+        };
+
+        let mut board = BoardConfig::default();
+        for size in [&mut board.width, &mut board.height, &mut board.center_size] {
+            *size = u16::from_le_bytes(take(&mut rest)?) as usize;
+        }
+
+        let color_count = u16::from_le_bytes(take(&mut rest)?);
+        let weights = (0..color_count)
+            .map(|_| take(&mut rest).map(u32::from_le_bytes))
+            .collect::<Result<_, _>>()?;
+        let palette = Palette::new(weights).map_err(ReplayError::InvalidPalette)?;
+
+        let mut replay = Self::new(seed, board, scoring, palette);
+        let mut tick = 0;
+        while !rest.is_empty() {
+            tick += read_varint(&mut rest)?;
+            let (&code, tail) = rest.split_first().ok_or(ReplayError::Truncated)?;
+            let input = ReplayInput::from_code(code).ok_or(ReplayError::UnknownInput(code))?;
+            replay.record(tick, input);
+            rest = tail;
+        }
+        Ok(replay)
+    }
+}
+
+// Steps through a replay, handing out inputs as their tick comes up
+#[derive(Debug, Clone, Default)]
+pub struct Playback {
+    replay: Replay,
+    next: usize,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Self { replay, next: 0 }
+    }
+
+    // Returns every input recorded at or before `tick` that has not been played yet
+    pub fn due(&mut self, tick: u64) -> Vec<ReplayInput> {
+        let start = self.next;
+        while self
+            .replay
+            .inputs
+            .get(self.next)
+            .is_some_and(|(input_tick, _)| *input_tick <= tick)
+        {
+            self.next += 1;
+        }
+        self.replay.inputs[start..self.next]
+            .iter()
+            .map(|(_, input)| *input)
+            .collect()
+    }
+}
+
+// Reads the next `N` bytes
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], ReplayError> {
+    let (head, rest) = bytes.split_at_checked(N).ok_or(ReplayError::Truncated)?;
+    *bytes = rest;
+    Ok(head.try_into().unwrap())
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Result<u64, ReplayError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (&byte, rest) = bytes.split_first().ok_or(ReplayError::Truncated)?;
+        *bytes = rest;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(ReplayError::Truncated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Replay {
+        let board = BoardConfig {
+            width: 20,
+            height: 14,
+            center_size: 6,
+            ..BoardConfig::default()
+        };
+        let scoring = ScoringModel {
+            base_points: 250,
+            chain_bonus: ChainBonus::Exponential,
+        };
+        let palette = Palette::new(vec![3, 1, 2]).unwrap();
+        let mut replay = Replay::new(-42, board, scoring, palette);
+        replay.record(0, ReplayInput::Spawn);
+        replay.record(0, ReplayInput::Command(Command::Move(Direction::Up)));
+        replay.record(200, ReplayInput::Command(Command::Shoot));
+        // Far enough apart to take several varint bytes
+        replay.record(1 << 40, ReplayInput::Spawn);
+        replay
+    }
+
+    #[test]
+    fn round_trips() {
+        let replay = sample();
+        assert_eq!(Replay::decode(&replay.encode()), Ok(replay));
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        let replay = sample();
+        let bytes = replay.encode();
+        let header = Replay {
+            inputs: Vec::new(),
+            ..replay.clone()
+        }
+        .encode()
+        .len();
+        for len in 0..header {
+            let expected = if len < 5 {
+                ReplayError::BadMagic
+            } else {
+                ReplayError::Truncated
+            };
+            assert_eq!(Replay::decode(&bytes[..len]), Err(expected), "{len} bytes");
+        }
+
+        // The last entry is a 6 byte delta and the input code
+        let last_entry = bytes.len() - 7;
+        assert_eq!(
+            Replay::decode(&bytes[..last_entry]).map(|replay| replay.inputs),
+            Ok(replay.inputs[..3].to_vec())
+        );
+        for len in last_entry + 1..bytes.len() {
+            assert_eq!(
+                Replay::decode(&bytes[..len]),
+                Err(ReplayError::Truncated),
+                "{len} bytes"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_versions_and_inputs() {
+        let mut bytes = sample().encode();
+        bytes[4] = VERSION - 1;
+        assert_eq!(
+            Replay::decode(&bytes),
+            Err(ReplayError::UnsupportedVersion(VERSION - 1))
+        );
+
+        let mut bytes = sample().encode();
+        *bytes.last_mut().unwrap() = 6;
+        assert_eq!(Replay::decode(&bytes), Err(ReplayError::UnknownInput(6)));
+        assert_eq!(Replay::decode(b"GOAP\x04"), Err(ReplayError::BadMagic));
+    }
+
+    #[test]
+    fn playback_hands_out_inputs_once_due() {
+        let mut playback = Playback::new(sample());
+        assert_eq!(
+            playback.due(0),
+            [
+                ReplayInput::Spawn,
+                ReplayInput::Command(Command::Move(Direction::Up))
+            ]
+        );
+        assert_eq!(playback.due(0), []);
+        assert_eq!(playback.due(199), []);
+        // Skipping past a tick still plays what was recorded on it
+        assert_eq!(playback.due(500), [ReplayInput::Command(Command::Shoot)]);
+        assert_eq!(playback.due(u64::MAX), [ReplayInput::Spawn]);
+        assert_eq!(playback.due(u64::MAX), []);
+    }
+}
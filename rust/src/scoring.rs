@@ -0,0 +1,61 @@
+// Points awarded for kills. Totals saturate instead of overflowing, however long a run lasts.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ChainBonus {
+    // Every kill in a shot is worth the same
+    Linear,
+    // The nth kill in a shot is worth n times as much
+    #[default]
+    Triangular,
+    // Each kill in a shot is worth twice as much as the one before
+    Exponential,
+}
+
+impl ChainBonus {
+    pub fn from_index(index: i64) -> Option<Self> {
+        match index {
+            0 => Some(Self::Linear),
+            1 => Some(Self::Triangular),
+            2 => Some(Self::Exponential),
+            _ => None,
+        }
+    }
+
+    pub fn index(self) -> i64 {
+        match self {
+            Self::Linear => 0,
+            Self::Triangular => 1,
+            Self::Exponential => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScoringModel {
+    // Points for the first kill of a shot
+    pub base_points: u64,
+    pub chain_bonus: ChainBonus,
+}
+
+impl Default for ScoringModel {
+    fn default() -> Self {
+        Self {
+            base_points: 100,
+            chain_bonus: ChainBonus::default(),
+        }
+    }
+}
+
+impl ScoringModel {
+    // Points for the kill at `chain` in a shot, where the first kill is 1
+    pub fn kill_points(&self, chain: u16) -> u64 {
+        let multiplier = match self.chain_bonus {
+            ChainBonus::Linear => 1,
+            ChainBonus::Triangular => chain as u64,
+            ChainBonus::Exponential => 1_u64
+                .checked_shl(chain.saturating_sub(1) as u32)
+                .unwrap_or(u64::MAX),
+        };
+        self.base_points.saturating_mul(multiplier)
+    }
+}
@@ -0,0 +1,262 @@
+// Touch controls: tapping a center cell walks the player there one step at a time,
+// swiping faces a direction and shoots. Both turn into the same commands the
+// keyboard gives, one per move, so replays can't tell them apart.
+
+use crate::board::BoardConfig;
+use crate::game::{Command, Direction, PlayerState, Position};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Gesture {
+    // Where the finger went down, in field coords
+    Tap { x: f32, y: f32 },
+    Swipe(Direction),
+}
+
+// Follows one finger from when it touches the screen until it lets go
+#[derive(Debug, Clone, PartialEq)]
+pub struct TouchTracker {
+    // How far a finger has to travel for a swipe instead of a tap
+    swipe_distance: f32,
+    // Finger being followed and where it went down
+    start: Option<(i32, f32, f32)>,
+}
+
+impl Default for TouchTracker {
+    fn default() -> Self {
+        Self::new(12.0)
+    }
+}
+
+impl TouchTracker {
+    pub fn new(swipe_distance: f32) -> Self {
+        Self {
+            swipe_distance,
+            start: None,
+        }
+    }
+
+    // Other fingers are ignored while one is down
+    pub fn press(&mut self, finger: i32, x: f32, y: f32) {
+        if self.start.is_none() {
+            self.start = Some((finger, x, y));
+        }
+    }
+
+    pub fn release(&mut self, finger: i32, x: f32, y: f32) -> Option<Gesture> {
+        let (start_finger, start_x, start_y) = self.start?;
+        if start_finger != finger {
+            return None;
+        }
+        self.start = None;
+
+        let (dx, dy) = (x - start_x, y - start_y);
+        if dx.hypot(dy) < self.swipe_distance {
+            return Some(Gesture::Tap {
+                x: start_x,
+                y: start_y,
+            });
+        }
+        Some(Gesture::Swipe(if dx.abs() >= dy.abs() {
+            if dx < 0.0 {
+                Direction::Left
+            } else {
+                Direction::Right
+            }
+        } else if dy < 0.0 {
+            Direction::Up
+        } else {
+            Direction::Down
+        }))
+    }
+}
+
+// What the last gesture asked for and is still being worked on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TouchGoal {
+    Walk(Position),
+    Shoot(Direction),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TouchInput {
+    pub tracker: TouchTracker,
+    goal: Option<TouchGoal>,
+}
+
+impl TouchInput {
+    // Walks to `position` if it is in the center, otherwise the tap is ignored
+    pub fn tap(&mut self, board: &BoardConfig, position: Position) {
+        if board.x_in_center(position.x) && board.y_in_center(position.y) {
+            self.goal = Some(TouchGoal::Walk(position));
+        }
+    }
+
+    pub fn swipe(&mut self, direction: Direction) {
+        self.goal = Some(TouchGoal::Shoot(direction));
+    }
+
+    // Drops the goal, e.g. when a key is pressed
+    pub fn cancel(&mut self) {
+        self.goal = None;
+    }
+
+    // The next command towards the goal. Only one is given at a time so every step
+    // starts from where the last one actually left the player.
+    pub fn next(&mut self, player: &PlayerState) -> Option<Command> {
+        match self.goal? {
+            TouchGoal::Walk(target) => {
+                let step = step_towards(player.position, target);
+                if step.is_none() {
+                    self.goal = None;
+                }
+                step.map(Command::Move)
+            }
+            // Moving along the lane keeps the player lined up with it
+            TouchGoal::Shoot(direction) if player.direction != direction => {
+                Some(Command::Move(direction))
+            }
+            TouchGoal::Shoot(_) => {
+                self.goal = None;
+                Some(Command::Shoot)
+            }
+        }
+    }
+}
+
+// Direction of one step from `from` to `to`, columns first
+pub fn step_towards(from: Position, to: Position) -> Option<Direction> {
+    if from.x != to.x {
+        Some(if to.x < from.x {
+            Direction::Left
+        } else {
+            Direction::Right
+        })
+    } else if from.y != to.y {
+        Some(if to.y < from.y {
+            Direction::Up
+        } else {
+            Direction::Down
+        })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::palette::Color;
+
+    fn player(x: usize, y: usize, direction: Direction) -> PlayerState {
+        PlayerState {
+            position: Position { x, y },
+            direction,
+            color: Color(0),
+        }
+    }
+
+    #[test]
+    fn short_moves_tap_where_the_finger_went_down() {
+        let mut tracker = TouchTracker::new(12.0);
+        tracker.press(0, 10.0, 20.0);
+        assert_eq!(
+            tracker.release(0, 18.0, 26.0),
+            Some(Gesture::Tap { x: 10.0, y: 20.0 })
+        );
+        // Released, so a second release gives nothing
+        assert_eq!(tracker.release(0, 18.0, 26.0), None);
+    }
+
+    #[test]
+    fn long_moves_swipe_along_the_larger_axis() {
+        for ((x, y), direction) in [
+            ((12.0, 0.0), Direction::Right),
+            ((-20.0, 5.0), Direction::Left),
+            ((3.0, -13.0), Direction::Up),
+            ((-9.0, 10.0), Direction::Down),
+        ] {
+            let mut tracker = TouchTracker::new(12.0);
+            tracker.press(1, 50.0, 50.0);
+            assert_eq!(
+                tracker.release(1, 50.0 + x, 50.0 + y),
+                Some(Gesture::Swipe(direction))
+            );
+        }
+    }
+
+    #[test]
+    fn other_fingers_are_ignored() {
+        let mut tracker = TouchTracker::new(12.0);
+        tracker.press(0, 0.0, 0.0);
+        tracker.press(1, 100.0, 100.0);
+        assert_eq!(tracker.release(1, 100.0, 100.0), None);
+        assert_eq!(
+            tracker.release(0, 0.0, 30.0),
+            Some(Gesture::Swipe(Direction::Down))
+        );
+    }
+
+    #[test]
+    fn steps_columns_first() {
+        let from = Position { x: 8, y: 5 };
+        let step = |x, y| step_towards(from, Position { x, y });
+        assert_eq!(step(7, 7), Some(Direction::Left));
+        assert_eq!(step(9, 4), Some(Direction::Right));
+        assert_eq!(step(8, 4), Some(Direction::Up));
+        assert_eq!(step(8, 7), Some(Direction::Down));
+        assert_eq!(step(8, 5), None);
+    }
+
+    #[test]
+    fn walks_to_a_tapped_center_cell() {
+        let board = BoardConfig::default();
+        let mut touch = TouchInput::default();
+        touch.tap(&board, Position { x: 9, y: 6 });
+
+        let mut state = player(7, 4, Direction::Up);
+        let mut steps = Vec::new();
+        while let Some(Command::Move(direction)) = touch.next(&state) {
+            let (dx, dy) = direction.delta();
+            state.position.x = state.position.x.wrapping_add_signed(dx);
+            state.position.y = state.position.y.wrapping_add_signed(dy);
+            steps.push(direction);
+        }
+        assert_eq!(
+            steps,
+            [
+                Direction::Right,
+                Direction::Right,
+                Direction::Down,
+                Direction::Down
+            ]
+        );
+        assert_eq!(touch.next(&state), None);
+    }
+
+    #[test]
+    fn ignores_taps_outside_the_center() {
+        let board = BoardConfig::default();
+        let mut touch = TouchInput::default();
+        touch.tap(&board, Position { x: 2, y: 6 });
+        assert_eq!(touch.next(&player(7, 4, Direction::Up)), None);
+    }
+
+    #[test]
+    fn swipes_turn_before_shooting() {
+        let mut touch = TouchInput::default();
+        touch.swipe(Direction::Left);
+        assert_eq!(
+            touch.next(&player(7, 4, Direction::Up)),
+            Some(Command::Move(Direction::Left))
+        );
+        assert_eq!(
+            touch.next(&player(7, 4, Direction::Left)),
+            Some(Command::Shoot)
+        );
+        assert_eq!(touch.next(&player(7, 4, Direction::Left)), None);
+
+        touch.swipe(Direction::Right);
+        touch.cancel();
+        assert_eq!(touch.next(&player(7, 4, Direction::Left)), None);
+    }
+}
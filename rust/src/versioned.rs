@@ -0,0 +1,84 @@
+// Text files the game saves in `user://`: a header line naming the file and its
+// format version, then lines of tab separated fields that each file reads its own way.
+
+use std::fmt;
+use std::str::Lines;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    MissingHeader,
+    UnsupportedVersion(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingHeader => write!(f, "file has the wrong header"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported version {version}"),
+        }
+    }
+}
+
+// The header line of a file, including the line break
+pub fn write_header(header: &str, version: u32) -> String {
+    format!("{header} {version}\n")
+}
+
+// Reads the header line, returning the format version and the lines after it.
+// Which versions are supported is up to the caller.
+pub fn read_versioned<'a>(text: &'a str, header: &str) -> Result<(u32, Lines<'a>), FormatError> {
+    let mut lines = text.lines();
+    let version = lines
+        .next()
+        .and_then(|line| line.strip_prefix(header)?.strip_prefix(' '))
+        .ok_or(FormatError::MissingHeader)?
+        .trim();
+    let version = version
+        .parse()
+        .map_err(|_| FormatError::UnsupportedVersion(version.to_string()))?;
+    Ok((version, lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "GOOP TEST";
+
+    #[test]
+    fn reads_the_version_and_the_lines_after_it() {
+        let text = write_header(HEADER, 3) + "a\tb\nc\n";
+        let (version, lines) = read_versioned(&text, HEADER).unwrap();
+        assert_eq!(version, 3);
+        assert_eq!(lines.collect::<Vec<_>>(), ["a\tb", "c"]);
+    }
+
+    #[test]
+    fn rejects_other_headers() {
+        for text in [
+            "",
+            "\n",
+            "garbage",
+            "GOOP TESTS 1",
+            "GOOP TEST1",
+            "goop test 1",
+        ] {
+            assert_eq!(
+                read_versioned(text, HEADER).err(),
+                Some(FormatError::MissingHeader),
+                "{text:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unreadable_versions() {
+        for version in ["", "x", "-1", "1.5"] {
+            let text = format!("{HEADER} {version}\n");
+            assert_eq!(
+                read_versioned(&text, HEADER).err(),
+                Some(FormatError::UnsupportedVersion(version.to_string()))
+            );
+        }
+    }
+}